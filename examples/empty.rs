@@ -1,28 +1,30 @@
-use easyfuse::{returns, Directory, DirectoryResource, EasyFuse, Request, Result};
-use fuse::FileType;
+use easyfuse::{returns, Directory, DirectoryResource, EasyFuse, FileHandle, Request, Result};
+use fuser::FileType;
+use std::time::SystemTime;
 
 struct Root;
 
 impl Directory for Root {
     fn getattr(&mut self, _req: &mut Request) -> Result<returns::Attr> {
-        Ok(returns::Attr::from(fuse::FileAttr {
+        Ok(returns::Attr::from(fuser::FileAttr {
             ino: 0,
             size: 0,
             blocks: 0,
-            atime: time::now().to_timespec(),
-            mtime: time::now().to_timespec(),
-            ctime: time::now().to_timespec(),
-            crtime: time::now().to_timespec(),
+            atime: SystemTime::now(),
+            mtime: SystemTime::now(),
+            ctime: SystemTime::now(),
+            crtime: SystemTime::now(),
             kind: FileType::Directory,
             perm: 0o555,
             nlink: 0,
             uid: 0,
             gid: 0,
             rdev: 0,
+            blksize: 0,
             flags: 0,
         }))
     }
-    fn readdir(&mut self, _req: &mut Request, _output: &mut Vec<returns::DirEntry>) -> Result<()> {
+    fn readdir(&mut self, _req: &mut Request, _fh: FileHandle, _output: &mut Vec<returns::DirEntry>) -> Result<()> {
         Ok(())
     }
 }
@@ -33,6 +35,6 @@ fn main() -> std::io::Result<()> {
     let mut fuse = EasyFuse::new();
     fuse.set_root(DirectoryResource(Root));
 
-    fuse::mount(fuse, &"test-mount", &[])?;
+    fuse.mount("test-mount", &[])?;
     Ok(())
 }
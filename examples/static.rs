@@ -1,20 +1,16 @@
-use easyfuse::{dir, file, returns, AttrBuilder, EasyFuse};
+use easyfuse::{dir, file, AttrBuilder, EasyFuse};
 
 fn main() -> std::io::Result<()> {
     env_logger::init();
 
     let mut fuse = EasyFuse::new();
 
-    let mut root = dir::StaticDirectory::new(returns::Attr::from(
-        AttrBuilder::directory().build()
-    ));
+    let mut root = dir::StaticDirectory::new(AttrBuilder::directory().build());
 
     root.bind(
         "README.md",
         fuse.register({
-            let mut file = file::StaticFile::new(returns::Attr::from(
-                AttrBuilder::file().build()
-            ));
+            let mut file = file::StaticFile::new(AttrBuilder::file().build());
             file.set_content("# I'm a fake file\n\n\
                               Can you believe it? I don't really exist... :O\n");
             file
@@ -23,11 +19,11 @@ fn main() -> std::io::Result<()> {
     root.bind(
         "secret",
         fuse.register({
-            let mut file = file::StaticFile::new(returns::Attr::from(
+            let mut file = file::StaticFile::new(
                 AttrBuilder::file()
-                    .with_perm(0o000)
+                    .with_mode("---------")
                     .build()
-            ));
+            );
             file.set_content("The meaning of life is 42.\n");
             file
         })
@@ -35,6 +31,6 @@ fn main() -> std::io::Result<()> {
 
     fuse.set_root(root);
 
-    fuse::mount(fuse, &"test-mount", &[])?;
+    fuse.mount("test-mount", &[])?;
     Ok(())
 }
@@ -0,0 +1,427 @@
+//! POSIX access control lists, which grant permissions to more users
+//! and groups than the mode bits can. They're stored in the
+//! `system.posix_acl_access` and `system.posix_acl_default` xattrs.
+
+use crate::{
+    Error,
+    Permissions,
+    Request,
+    Resource,
+    Result,
+    XattrFlags,
+};
+
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    ffi::{OsStr, OsString},
+};
+
+use fuser::{FileAttr, FileType};
+
+/// The xattr holding the ACL that's checked when accessing a file
+pub const ACCESS_XATTR: &str = "system.posix_acl_access";
+/// The xattr holding the ACL that new files in a directory inherit
+pub const DEFAULT_XATTR: &str = "system.posix_acl_default";
+
+const VERSION: u32 = 2;
+const UNDEFINED_ID: u32 = u32::max_value();
+
+const TAG_USER_OBJ: u16 = 0x01;
+const TAG_USER: u16 = 0x02;
+const TAG_GROUP_OBJ: u16 = 0x04;
+const TAG_GROUP: u16 = 0x08;
+const TAG_MASK: u16 = 0x10;
+const TAG_OTHER: u16 = 0x20;
+
+/// Who an ACL entry applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Tag {
+    /// The owner of the file, like the user digit of the mode
+    UserObj,
+    /// A named user
+    User(u32),
+    /// The group of the file, like the group digit of the mode
+    GroupObj,
+    /// A named group
+    Group(u32),
+    /// The most that any named entry or the group entry can grant
+    Mask,
+    /// Everyone else, like the other digit of the mode
+    Other,
+}
+impl Tag {
+    fn to_raw(self) -> (u16, u32) {
+        match self {
+            Tag::UserObj => (TAG_USER_OBJ, UNDEFINED_ID),
+            Tag::User(uid) => (TAG_USER, uid),
+            Tag::GroupObj => (TAG_GROUP_OBJ, UNDEFINED_ID),
+            Tag::Group(gid) => (TAG_GROUP, gid),
+            Tag::Mask => (TAG_MASK, UNDEFINED_ID),
+            Tag::Other => (TAG_OTHER, UNDEFINED_ID),
+        }
+    }
+    fn from_raw(tag: u16, id: u32) -> Option<Self> {
+        match tag {
+            TAG_USER_OBJ => Some(Tag::UserObj),
+            TAG_USER => Some(Tag::User(id)),
+            TAG_GROUP_OBJ => Some(Tag::GroupObj),
+            TAG_GROUP => Some(Tag::Group(id)),
+            TAG_MASK => Some(Tag::Mask),
+            TAG_OTHER => Some(Tag::Other),
+            _ => None,
+        }
+    }
+}
+
+/// A single entry of an ACL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Who this entry applies to
+    pub tag: Tag,
+    /// What they may do
+    pub perms: Permissions,
+}
+
+/// An access control list. Entries are kept sorted and unique by tag,
+/// which is how the kernel expects them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    entries: Vec<Entry>,
+}
+impl Acl {
+    /// Create an empty ACL. Note that it's not valid until it has at
+    /// least the `UserObj`, `GroupObj` and `Other` entries.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Create the minimal ACL equivalent to the permission bits of a
+    /// mode
+    #[allow(clippy::integer_arithmetic)]
+    pub fn from_mode(perm: u16) -> Self {
+        let digit = |shift: u16| Permissions::from_bits_truncate(u8::try_from((perm >> shift) & 0o7).unwrap_or(0));
+        let mut acl = Self::new();
+        acl.set(Tag::UserObj, digit(6));
+        acl.set(Tag::GroupObj, digit(3));
+        acl.set(Tag::Other, digit(0));
+        acl
+    }
+
+    /// All entries, sorted by tag
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+    /// The permissions of an entry, if it's there
+    pub fn get(&self, tag: Tag) -> Option<Permissions> {
+        self.entries.binary_search_by_key(&tag, |entry| entry.tag).ok()
+            .map(|i| self.entries[i].perms)
+    }
+    /// Add an entry, or replace the permissions of an existing one
+    pub fn set(&mut self, tag: Tag, perms: Permissions) {
+        match self.entries.binary_search_by_key(&tag, |entry| entry.tag) {
+            Ok(i) => self.entries[i].perms = perms,
+            Err(i) => self.entries.insert(i, Entry { tag, perms }),
+        }
+    }
+    /// Remove an entry, returning its permissions
+    pub fn remove(&mut self, tag: Tag) -> Option<Permissions> {
+        let i = self.entries.binary_search_by_key(&tag, |entry| entry.tag).ok()?;
+        Some(self.entries.remove(i).perms)
+    }
+
+    /// Returns true if this ACL has the required entries, and a mask
+    /// if there are any named users or groups
+    pub fn is_valid(&self) -> bool {
+        let named = self.entries.iter().any(|entry| matches!(entry.tag, Tag::User(_) | Tag::Group(_)));
+        self.get(Tag::UserObj).is_some()
+            && self.get(Tag::GroupObj).is_some()
+            && self.get(Tag::Other).is_some()
+            && (!named || self.get(Tag::Mask).is_some())
+    }
+
+    /// Parse the value of an ACL xattr, raising `EINVAL` if it's
+    /// malformed or invalid
+    pub fn from_xattr(value: &[u8]) -> Result<Self> {
+        let einval = || Error::Errno(libc::EINVAL);
+        let header = value.get(..4).ok_or_else(einval)?;
+        if u32::from_le_bytes([header[0], header[1], header[2], header[3]]) != VERSION {
+            return Err(Error::Errno(libc::EOPNOTSUPP));
+        }
+
+        let raws = value[4..].chunks_exact(8);
+        if !raws.remainder().is_empty() {
+            return Err(einval());
+        }
+        let mut acl = Self::new();
+        for raw in raws {
+            let tag = u16::from_le_bytes([raw[0], raw[1]]);
+            let perms = u16::from_le_bytes([raw[2], raw[3]]);
+            let id = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]);
+
+            let tag = Tag::from_raw(tag, id).ok_or_else(einval)?;
+            let perms = u8::try_from(perms).ok()
+                .and_then(Permissions::from_bits)
+                .ok_or_else(einval)?;
+            if acl.get(tag).is_some() {
+                return Err(einval());
+            }
+            acl.set(tag, perms);
+        }
+        if !acl.is_valid() {
+            return Err(einval());
+        }
+        Ok(acl)
+    }
+    /// Serialize this ACL as the value of an ACL xattr
+    pub fn to_xattr(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(self.entries.len().saturating_mul(8).saturating_add(4));
+        value.extend_from_slice(&VERSION.to_le_bytes());
+        for entry in &self.entries {
+            let (tag, id) = entry.tag.to_raw();
+            value.extend_from_slice(&tag.to_le_bytes());
+            value.extend_from_slice(&u16::from(entry.perms.bits()).to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        value
+    }
+
+    /// Update the permission bits of `perm` to match this ACL. With a
+    /// mask, the group digit shows the mask rather than the group
+    /// entry, like `ls -l` expects.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn apply_to_mode(&self, perm: u16) -> u16 {
+        let digit = |tag: Tag| self.get(tag).map_or(0, |perms| u16::from(perms.bits()));
+        let group = if self.get(Tag::Mask).is_some() { Tag::Mask } else { Tag::GroupObj };
+        (perm & !0o777) | digit(Tag::UserObj) << 6 | digit(group) << 3 | digit(Tag::Other)
+    }
+
+    /// Returns true if this ACL grants the caller of `req` the
+    /// `required` permissions on a file with `attrs`. Root gets the
+    /// same override as with `Request::perms`.
+    pub fn permits(&self, req: &Request, attrs: &FileAttr, required: Permissions) -> bool {
+        let uid = req.uid();
+        if uid == 0 {
+            return req.perms(attrs).contains(required);
+        }
+        self.grants(uid, req.gid(), &req.groups(), attrs, required)
+    }
+    /// Returns true if this ACL grants a user with `uid`, `gid` and
+    /// the supplementary `groups` the `required` permissions on a
+    /// file with `attrs`, without any override for root
+    fn grants(&self, uid: u32, gid: u32, groups: &[u32], attrs: &FileAttr, required: Permissions) -> bool {
+        let entry = |tag: Tag| self.get(tag).unwrap_or_else(Permissions::empty);
+        let mask = self.get(Tag::Mask).unwrap_or_else(Permissions::all);
+
+        if uid == attrs.uid {
+            return entry(Tag::UserObj).contains(required);
+        }
+        if let Some(perms) = self.get(Tag::User(uid)) {
+            return (perms & mask).contains(required);
+        }
+
+        // Access is granted if any of the caller's groups has all the
+        // permissions, not if they add up to them
+        let in_group = |group: u32| group == gid || groups.contains(&group);
+        let mut matched = false;
+        for entry in &self.entries {
+            let applies = match entry.tag {
+                Tag::GroupObj => in_group(attrs.gid),
+                Tag::Group(group) => in_group(group),
+                _ => false,
+            };
+            if applies {
+                if (entry.perms & mask).contains(required) {
+                    return true;
+                }
+                matched = true;
+            }
+        }
+        if matched {
+            return false;
+        }
+
+        entry(Tag::Other).contains(required)
+    }
+}
+
+/// Read the access ACL of a resource through its xattrs, if it has one.
+/// Resources report a missing xattr in all sorts of ways, so any error
+/// other than `EIO` counts as not having an ACL, instead of failing
+/// the operation that needed it.
+pub(crate) fn access_acl(resource: &mut dyn Resource, req: &mut Request) -> Result<Option<Acl>> {
+    match resource.getxattr(req, OsStr::new(ACCESS_XATTR)) {
+        Ok(value) => Acl::from_xattr(&value).map(Some),
+        Err(err) if err.errno() == libc::EIO => Err(err),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Storage for the ACL xattrs of the built-in resources. Forward
+/// the xattr operations here, and it'll handle the ACL ones.
+#[derive(Debug, Clone, Default)]
+pub struct AclXattrs {
+    access: Option<Acl>,
+    default: Option<Acl>,
+}
+impl AclXattrs {
+    /// Create an instance with no ACLs
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The ACL that's checked when accessing the file
+    pub fn access_acl(&self) -> Option<&Acl> {
+        self.access.as_ref()
+    }
+    /// Set the ACL that's checked when accessing the file. This does
+    /// not update the mode, see `Acl::apply_to_mode`.
+    pub fn set_access_acl(&mut self, acl: Option<Acl>) {
+        self.access = acl;
+    }
+    /// The ACL that new files in the directory inherit
+    pub fn default_acl(&self) -> Option<&Acl> {
+        self.default.as_ref()
+    }
+    /// Set the ACL that new files in the directory inherit
+    pub fn set_default_acl(&mut self, acl: Option<Acl>) {
+        self.default = acl;
+    }
+
+    fn slot(&mut self, attrs: &FileAttr, name: &OsStr) -> Result<&mut Option<Acl>> {
+        if name == ACCESS_XATTR {
+            Ok(&mut self.access)
+        } else if name == DEFAULT_XATTR {
+            if attrs.kind != FileType::Directory {
+                return Err(Error::PermissionDenied);
+            }
+            Ok(&mut self.default)
+        } else {
+            Err(Error::Errno(libc::EOPNOTSUPP))
+        }
+    }
+    /// Only the owner and root may change ACLs
+    fn ensure_owner(req: &Request, attrs: &FileAttr) -> Result<()> {
+        let uid = req.uid();
+        if uid == 0 || uid == attrs.uid {
+            Ok(())
+        } else {
+            Err(Error::Errno(libc::EPERM))
+        }
+    }
+
+    /// See `Resource::getxattr`
+    pub fn getxattr(&mut self, attrs: &FileAttr, name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        match self.slot(attrs, name) {
+            Ok(slot) => slot.as_ref().map(|acl| Cow::Owned(acl.to_xattr())).ok_or(Error::Errno(libc::ENODATA)),
+            Err(_) => Err(Error::Errno(libc::ENODATA)),
+        }
+    }
+    /// See `Resource::setxattr`. Setting the access ACL also updates
+    /// the permission bits of `attrs`.
+    pub fn setxattr(&mut self, req: &Request, attrs: &mut FileAttr, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        let slot = self.slot(attrs, name)?;
+        Self::ensure_owner(req, attrs)?;
+        if flags.contains(XattrFlags::CREATE) && slot.is_some() {
+            return Err(Error::Errno(libc::EEXIST));
+        }
+        if flags.contains(XattrFlags::REPLACE) && slot.is_none() {
+            return Err(Error::Errno(libc::ENODATA));
+        }
+
+        let acl = Acl::from_xattr(value)?;
+        if name == ACCESS_XATTR {
+            attrs.perm = acl.apply_to_mode(attrs.perm);
+        }
+        *slot = Some(acl);
+        Ok(())
+    }
+    /// See `Resource::listxattr`
+    pub fn listxattr(&self, output: &mut Vec<OsString>) {
+        if self.access.is_some() {
+            output.push(ACCESS_XATTR.into());
+        }
+        if self.default.is_some() {
+            output.push(DEFAULT_XATTR.into());
+        }
+    }
+    /// See `Resource::removexattr`
+    pub fn removexattr(&mut self, req: &Request, attrs: &FileAttr, name: &OsStr) -> Result<()> {
+        let slot = self.slot(attrs, name).map_err(|_| Error::Errno(libc::ENODATA))?;
+        Self::ensure_owner(req, attrs)?;
+        slot.take().map(drop).ok_or(Error::Errno(libc::ENODATA))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AttrBuilder, EasyFuse, Inode};
+
+    const RW: Permissions = Permissions::from_bits_truncate(0o6);
+
+    fn named() -> Acl {
+        let mut acl = Acl::from_mode(0o640);
+        acl.set(Tag::User(1001), Permissions::all());
+        acl.set(Tag::Group(2001), Permissions::READ);
+        acl.set(Tag::Mask, RW);
+        acl
+    }
+    fn attrs() -> FileAttr {
+        AttrBuilder::file().with_uid(1000).with_gid(2000).build().inner
+    }
+
+    #[test]
+    fn xattr_round_trip() {
+        let acl = named();
+        assert_eq!(Acl::from_xattr(&acl.to_xattr()).unwrap(), acl);
+    }
+    #[test]
+    fn rejects_duplicate_tags() {
+        let mut value = Acl::from_mode(0o640).to_xattr();
+        let owner = value[4..12].to_vec();
+        value.extend_from_slice(&owner);
+        assert_eq!(Acl::from_xattr(&value).unwrap_err().errno(), libc::EINVAL);
+    }
+    #[test]
+    fn rejects_named_entries_without_mask() {
+        let mut acl = named();
+        acl.remove(Tag::Mask);
+        assert!(!acl.is_valid());
+        assert_eq!(Acl::from_xattr(&acl.to_xattr()).unwrap_err().errno(), libc::EINVAL);
+    }
+    #[test]
+    fn mask_limits_named_users() {
+        let acl = named();
+        assert!(acl.grants(1001, 3000, &[], &attrs(), RW));
+        assert!(!acl.grants(1001, 3000, &[], &attrs(), Permissions::EXECUTE));
+        // The owner isn't limited by the mask
+        assert!(acl.grants(1000, 3000, &[], &attrs(), RW));
+    }
+    #[test]
+    fn groups_dont_add_up() {
+        let mut acl = named();
+        acl.set(Tag::Group(2002), Permissions::WRITE);
+        acl.set(Tag::Other, RW);
+        // READ from 2001 and WRITE from 2002 don't make READ | WRITE,
+        // and matching a group rules out the other entry
+        assert!(!acl.grants(1002, 2001, &[2002], &attrs(), RW));
+        assert!(acl.grants(1002, 2001, &[2002], &attrs(), Permissions::READ));
+        assert!(acl.grants(1002, 3000, &[], &attrs(), RW));
+    }
+    #[test]
+    fn only_eio_fails_acl_lookup() {
+        struct Failing(libc::c_int);
+        impl Resource for Failing {
+            fn getxattr(&'_ mut self, _req: &mut Request, _name: &OsStr) -> Result<Cow<'_, [u8]>> {
+                Err(Error::Errno(self.0))
+            }
+        }
+
+        let mut fs = EasyFuse::default();
+        let mut req = fs.internal_request(Inode(2));
+        for &errno in &[libc::ENODATA, libc::ENOSYS, libc::EACCES, libc::ENOENT] {
+            assert_eq!(access_acl(&mut Failing(errno), &mut req).unwrap(), None);
+        }
+        assert_eq!(access_acl(&mut Failing(libc::EIO), &mut req).unwrap_err().errno(), libc::EIO);
+    }
+}
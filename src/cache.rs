@@ -0,0 +1,169 @@
+//! How long attributes and directory entries may be cached, both by
+//! the kernel and by `EasyFuse` itself, so that expensive resources
+//! aren't asked for the same attributes over and over
+
+use crate::{returns, Inode};
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    time::{Duration, Instant},
+};
+
+use fuser::FileType;
+
+/// Expired entries are only swept once there are this many
+const SWEEP_THRESHOLD: usize = 4096;
+
+/// Chooses the TTL of attributes and entries that a resource returns
+/// with a zero TTL. A resource can still choose its own TTL by
+/// returning a non-zero one. By default, everything has a zero TTL,
+/// which means nothing is cached.
+#[derive(Debug, Clone, Default)]
+pub struct CachePolicy {
+    file: Duration,
+    directory: Duration,
+    symlink: Duration,
+    other: Duration,
+    overrides: BTreeMap<Inode, Duration>,
+}
+impl CachePolicy {
+    /// Same as `Self::default()`
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A policy using the same TTL for every file type
+    pub fn uniform(ttl: Duration) -> Self {
+        Self {
+            file: ttl,
+            directory: ttl,
+            symlink: ttl,
+            other: ttl,
+            overrides: BTreeMap::new(),
+        }
+    }
+    /// A chaining function to set the TTL of one file type. Files
+    /// other than regular files, directories and symlinks all share
+    /// the same TTL.
+    pub fn with_kind(mut self, kind: FileType, ttl: Duration) -> Self {
+        *self.kind_mut(kind) = ttl;
+        self
+    }
+    /// A chaining function to set the TTL of a single inode, see
+    /// `set_override`
+    pub fn with_override(mut self, inode: Inode, ttl: Duration) -> Self {
+        self.set_override(inode, Some(ttl));
+        self
+    }
+    /// Use `ttl` for `inode` instead of the TTL of its file type, or
+    /// go back to the file type's TTL if `None`
+    pub fn set_override(&mut self, inode: Inode, ttl: Option<Duration>) {
+        match ttl {
+            Some(ttl) => self.overrides.insert(inode, ttl),
+            None => self.overrides.remove(&inode),
+        };
+    }
+
+    /// The TTL of `inode`, which is of type `kind`
+    pub fn ttl(&self, inode: Inode, kind: FileType) -> Duration {
+        self.overrides.get(&inode).copied().unwrap_or(match kind {
+            FileType::RegularFile => self.file,
+            FileType::Directory => self.directory,
+            FileType::Symlink => self.symlink,
+            _ => self.other,
+        })
+    }
+
+    fn kind_mut(&mut self, kind: FileType) -> &mut Duration {
+        match kind {
+            FileType::RegularFile => &mut self.file,
+            FileType::Directory => &mut self.directory,
+            FileType::Symlink => &mut self.symlink,
+            _ => &mut self.other,
+        }
+    }
+}
+
+/// Attributes and entries that have been returned to the kernel and
+/// are still valid, by the time they expire
+#[derive(Debug, Default)]
+pub(crate) struct AttrCache {
+    attrs: BTreeMap<Inode, (Instant, returns::Attr)>,
+    entries: BTreeMap<(Inode, OsString), (Instant, returns::Entry)>,
+}
+impl AttrCache {
+    /// The cached attributes of `inode`, along with how long they
+    /// remain valid
+    pub(crate) fn attr(&self, inode: Inode) -> Option<(returns::Attr, Duration)> {
+        let (expires, attr) = self.attrs.get(&inode)?;
+        remaining(*expires).map(|ttl| (*attr, ttl))
+    }
+    /// The cached entry `name` in `parent`, along with how long it
+    /// remains valid
+    pub(crate) fn entry(&self, parent: Inode, name: &OsStr) -> Option<(returns::Entry, Duration)> {
+        let (expires, entry) = self.entries.get(&(parent, name.to_os_string()))?;
+        remaining(*expires).map(|ttl| (*entry, ttl))
+    }
+    /// Cache the attributes of `inode` for `ttl`
+    pub(crate) fn insert_attr(&mut self, inode: Inode, attr: returns::Attr, ttl: Duration) {
+        let now = Instant::now();
+        if let Some(expires) = expiry(now, ttl) {
+            if self.attrs.len() >= SWEEP_THRESHOLD {
+                self.attrs.retain(|_, (expires, _)| *expires > now);
+            }
+            self.attrs.insert(inode, (expires, attr));
+        }
+    }
+    /// Cache the entry `name` in `parent` for `ttl`, along with the
+    /// attributes of the inode it points to
+    pub(crate) fn insert_entry(&mut self, parent: Inode, name: &OsStr, entry: returns::Entry, ttl: Duration) {
+        let now = Instant::now();
+        if let Some(expires) = expiry(now, ttl) {
+            if self.entries.len() >= SWEEP_THRESHOLD {
+                self.entries.retain(|_, (expires, _)| *expires > now);
+            }
+            self.entries.insert((parent, name.to_os_string()), (expires, entry));
+            self.insert_attr(Inode(entry.attr.inner.ino), entry.attr, ttl);
+        }
+    }
+
+    /// Forget the attributes of `inode`, along with all entries in it
+    /// or pointing to it
+    pub(crate) fn invalidate(&mut self, inode: Inode) {
+        self.attrs.remove(&inode);
+        self.entries.retain(|(parent, _), (_, entry)| *parent != inode && entry.attr.inner.ino != inode.0);
+    }
+    /// Forget the attributes of `inode`, along with all entries
+    /// pointing to it, which contain the same attributes
+    pub(crate) fn invalidate_attr(&mut self, inode: Inode) {
+        self.attrs.remove(&inode);
+        self.entries.retain(|_, (_, entry)| entry.attr.inner.ino != inode.0);
+    }
+    /// Forget the entry `name` in `parent`
+    pub(crate) fn invalidate_entry(&mut self, parent: Inode, name: &OsStr) {
+        self.entries.remove(&(parent, name.to_os_string()));
+    }
+    /// Forget everything
+    pub(crate) fn clear(&mut self) {
+        self.attrs.clear();
+        self.entries.clear();
+    }
+}
+
+/// When something cached now for `ttl` expires, or `None` if it
+/// shouldn't be cached at all
+fn expiry(now: Instant, ttl: Duration) -> Option<Instant> {
+    if ttl == Duration::from_secs(0) {
+        return None;
+    }
+    now.checked_add(ttl)
+}
+/// How long until `expires`, or `None` if it already has
+fn remaining(expires: Instant) -> Option<Duration> {
+    let now = Instant::now();
+    if expires > now {
+        Some(expires.duration_since(now))
+    } else {
+        None
+    }
+}
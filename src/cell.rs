@@ -1,9 +1,21 @@
 //! A wrapper for `Rc<RefCell<dyn Resource>>` that implements
 //! `From<Resource>`
+//!
+//! While a resource is being called, `EasyFuse` holds a mutable
+//! borrow of its cell. A resource that needs to inspect its siblings
+//! can resolve them through `Request::fs`, but should borrow them
+//! with `ResourceCell::try_borrow_resource` rather than `borrow_mut`:
+//! if it ever reaches itself, for example because a directory was
+//! bound inside itself, that returns an error instead of panicking.
+//! Such calls should also go through `Request::call`, so that a
+//! sibling that panics gets poisoned instead of the caller.
+//! Resources that share state between several inodes should keep it
+//! in a separate `RefCell`, and drop any borrow of it before calling
+//! into other resources.
 
-use crate::{dir, file, DirectoryResource, FileResource, Resource};
+use crate::{dir, file, DirectoryResource, Error, FileResource, Resource, Result};
 use std::{
-    cell::RefCell,
+    cell::{RefCell, RefMut},
     fmt,
     ops::Deref,
     rc::Rc,
@@ -14,6 +26,14 @@ use std::{
 #[derive(Clone)]
 pub struct ResourceCell(pub Rc<RefCell<dyn Resource>>);
 
+impl ResourceCell {
+    /// Mutably borrow the resource, or fail with `EDEADLK` if it's
+    /// already borrowed, which means it's being called re-entrantly
+    pub fn try_borrow_resource(&self) -> Result<RefMut<'_, dyn Resource + 'static>> {
+        self.0.try_borrow_mut().map_err(|_| Error::Errno(libc::EDEADLK))
+    }
+}
+
 impl<R> From<R> for ResourceCell
 where
     R: Resource + 'static
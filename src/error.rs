@@ -0,0 +1,107 @@
+//! The error type returned from all resource functions, which gets
+//! converted to an errno value before replying to the kernel
+
+use std::{
+    error::Error as StdError,
+    fmt,
+    io,
+};
+
+use libc::c_int;
+use log::{trace, warn};
+
+/// An error that can be returned from a resource
+#[derive(Debug)]
+pub enum Error {
+    /// The file doesn't exist, see `ENOENT`
+    NotFound,
+    /// The user lacks permission for this operation, see `EACCES`
+    PermissionDenied,
+    /// An I/O error, for example from accessing an underlying file.
+    /// Maps to its OS error if there is one, otherwise to the closest
+    /// errno for its kind.
+    Io(io::Error),
+    /// A raw errno value
+    Errno(c_int),
+    /// Any other error, which maps to `EIO`
+    Custom(Box<dyn StdError + Send + Sync>),
+}
+impl Error {
+    /// Wrap any error as a custom error
+    pub fn custom<E>(err: E) -> Self
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>
+    {
+        Error::Custom(err.into())
+    }
+
+    /// The errno value this error is reported as
+    pub fn errno(&self) -> c_int {
+        match self {
+            Error::NotFound => libc::ENOENT,
+            Error::PermissionDenied => libc::EACCES,
+            Error::Io(err) => err.raw_os_error().unwrap_or_else(|| match err.kind() {
+                io::ErrorKind::NotFound => libc::ENOENT,
+                io::ErrorKind::PermissionDenied => libc::EACCES,
+                io::ErrorKind::AlreadyExists => libc::EEXIST,
+                io::ErrorKind::InvalidInput => libc::EINVAL,
+                io::ErrorKind::Interrupted => libc::EINTR,
+                io::ErrorKind::WouldBlock => libc::EAGAIN,
+                io::ErrorKind::TimedOut => libc::ETIMEDOUT,
+                _ => libc::EIO,
+            }),
+            Error::Errno(errno) => *errno,
+            Error::Custom(_) => libc::EIO,
+        }
+    }
+
+    /// Log this error before it's discarded in favor of its errno.
+    /// Plain errno values are common and only traced, while errors
+    /// with more context are warned about along with their sources.
+    pub(crate) fn log(&self) {
+        match self {
+            Error::NotFound | Error::PermissionDenied | Error::Errno(_) => {
+                trace!("replying with error: {}", self);
+            },
+            Error::Io(_) | Error::Custom(_) => {
+                warn!("replying with errno {}: {}", self.errno(), self);
+                let mut source = self.source();
+                while let Some(err) = source {
+                    warn!("caused by: {}", err);
+                    source = err.source();
+                }
+            },
+        }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "no such file or directory"),
+            Error::PermissionDenied => write!(f, "permission denied"),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Errno(errno) => write!(f, "{}", io::Error::from_raw_os_error(*errno)),
+            Error::Custom(err) => write!(f, "{}", err),
+        }
+    }
+}
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io(err) => err.source(),
+            Error::Custom(err) => err.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<c_int> for Error {
+    fn from(errno: c_int) -> Self {
+        Error::Errno(errno)
+    }
+}
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
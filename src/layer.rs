@@ -0,0 +1,191 @@
+//! Middleware that wraps every resource, for behavior like logging or
+//! access control that shouldn't have to be written into each one.
+//!
+//! A `Layer` sees every call to a resource along with the resource
+//! itself, and decides whether and how to forward it. Layers are added
+//! to `EasyFuse` with `EasyFuse::add_layer`, and are applied whenever
+//! an inode is resolved. Since layers can be called re-entrantly (for
+//! example by a directory looking up its children), they only get
+//! `&self` and should keep any state in a `Cell` or `RefCell`.
+
+use crate::{
+    returns,
+    FileHandle,
+    Inode,
+    OpenFlags,
+    Request,
+    Resource,
+    ResourceCell,
+    Result,
+    XattrFlags,
+};
+
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    fmt,
+    path::Path,
+    rc::Rc,
+};
+
+/// Intercepts calls to a resource. Every method receives the wrapped
+/// resource as `inner`, and by default just forwards the call to it.
+/// `req.inode` tells which resource is being called.
+#[allow(unused_variables)]
+pub trait Layer {
+    /// See `Resource::getattr`
+    fn getattr(&self, req: &mut Request, inner: &mut dyn Resource) -> Result<returns::Attr> {
+        inner.getattr(req)
+    }
+
+    // Directory operations
+
+    /// See `Resource::opendir`
+    fn opendir(&self, req: &mut Request, inner: &mut dyn Resource, flags: u32) -> Result<FileHandle> {
+        inner.opendir(req, flags)
+    }
+    /// See `Resource::releasedir`
+    fn releasedir(&self, req: &mut Request, inner: &mut dyn Resource, fh: FileHandle, flags: u32) -> Result<()> {
+        inner.releasedir(req, fh, flags)
+    }
+    /// See `Resource::lookup`
+    fn lookup(&self, req: &mut Request, inner: &mut dyn Resource, path: &OsStr) -> Result<returns::Entry> {
+        inner.lookup(req, path)
+    }
+    /// See `Resource::readdir`
+    fn readdir(&self, req: &mut Request, inner: &mut dyn Resource, fh: FileHandle, output: &mut Vec<returns::DirEntry>) -> Result<()> {
+        inner.readdir(req, fh, output)
+    }
+    /// See `Resource::readdir_from`
+    fn readdir_from(&self, req: &mut Request, inner: &mut dyn Resource, fh: FileHandle, offset: i64, output: &mut returns::DirWriter) -> Result<()> {
+        inner.readdir_from(req, fh, offset, output)
+    }
+    /// See `Resource::symlink`
+    fn symlink(&self, req: &mut Request, inner: &mut dyn Resource, path: &OsStr, link: &Path) -> Result<returns::Entry> {
+        inner.symlink(req, path, link)
+    }
+    /// See `Resource::link`
+    fn link(&self, req: &mut Request, inner: &mut dyn Resource, path: &OsStr, inode: Inode) -> Result<()> {
+        inner.link(req, path, inode)
+    }
+    /// See `Resource::unlink`
+    fn unlink(&self, req: &mut Request, inner: &mut dyn Resource, path: &OsStr) -> Result<Inode> {
+        inner.unlink(req, path)
+    }
+
+    // File operations
+
+    /// See `Resource::open`
+    fn open(&self, req: &mut Request, inner: &mut dyn Resource, flags: OpenFlags) -> Result<returns::Opened> {
+        inner.open(req, flags)
+    }
+    /// See `Resource::close`
+    fn close(&self, req: &mut Request, inner: &mut dyn Resource, fh: FileHandle, flags: OpenFlags) -> Result<()> {
+        inner.close(req, fh, flags)
+    }
+    /// See `Resource::read`
+    fn read<'a>(&self, req: &mut Request, inner: &'a mut dyn Resource, fh: FileHandle, offset: i64, len: u32) -> Result<Cow<'a, [u8]>> {
+        inner.read(req, fh, offset, len)
+    }
+
+    // Xattr operations
+
+    /// See `Resource::getxattr`
+    fn getxattr<'a>(&self, req: &mut Request, inner: &'a mut dyn Resource, name: &OsStr) -> Result<Cow<'a, [u8]>> {
+        inner.getxattr(req, name)
+    }
+    /// See `Resource::setxattr`
+    fn setxattr(&self, req: &mut Request, inner: &mut dyn Resource, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        inner.setxattr(req, name, value, flags)
+    }
+    /// See `Resource::listxattr`
+    fn listxattr(&self, req: &mut Request, inner: &mut dyn Resource, output: &mut Vec<OsString>) -> Result<()> {
+        inner.listxattr(req, output)
+    }
+    /// See `Resource::removexattr`
+    fn removexattr(&self, req: &mut Request, inner: &mut dyn Resource, name: &OsStr) -> Result<()> {
+        inner.removexattr(req, name)
+    }
+}
+
+/// A resource that passes every call through a layer before reaching
+/// the inner resource. Data borrowed from the inner resource has to be
+/// copied, since the inner borrow ends when the call returns.
+pub(crate) struct Layered {
+    pub(crate) layer: Rc<dyn Layer>,
+    pub(crate) inner: ResourceCell,
+}
+impl fmt::Debug for Layered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Layered")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+impl Resource for Layered {
+    fn type_name(&self) -> &'static str {
+        self.inner.try_borrow().map_or("Layered", |inner| inner.type_name())
+    }
+
+    fn getattr(&mut self, req: &mut Request) -> Result<returns::Attr> {
+        self.layer.getattr(req, &mut *self.inner.try_borrow_resource()?)
+    }
+
+    // Directory operations
+
+    fn opendir(&mut self, req: &mut Request, flags: u32) -> Result<FileHandle> {
+        self.layer.opendir(req, &mut *self.inner.try_borrow_resource()?, flags)
+    }
+    fn releasedir(&mut self, req: &mut Request, fh: FileHandle, flags: u32) -> Result<()> {
+        self.layer.releasedir(req, &mut *self.inner.try_borrow_resource()?, fh, flags)
+    }
+    fn lookup(&mut self, req: &mut Request, path: &OsStr) -> Result<returns::Entry> {
+        self.layer.lookup(req, &mut *self.inner.try_borrow_resource()?, path)
+    }
+    fn readdir(&mut self, req: &mut Request, fh: FileHandle, output: &mut Vec<returns::DirEntry>) -> Result<()> {
+        self.layer.readdir(req, &mut *self.inner.try_borrow_resource()?, fh, output)
+    }
+    fn readdir_from(&mut self, req: &mut Request, fh: FileHandle, offset: i64, output: &mut returns::DirWriter) -> Result<()> {
+        self.layer.readdir_from(req, &mut *self.inner.try_borrow_resource()?, fh, offset, output)
+    }
+    fn symlink(&'_ mut self, req: &mut Request, path: &OsStr, link: &Path) -> Result<returns::Entry> {
+        self.layer.symlink(req, &mut *self.inner.try_borrow_resource()?, path, link)
+    }
+    fn link(&mut self, req: &mut Request, path: &OsStr, inode: Inode) -> Result<()> {
+        self.layer.link(req, &mut *self.inner.try_borrow_resource()?, path, inode)
+    }
+    fn unlink(&mut self, req: &mut Request, path: &OsStr) -> Result<Inode> {
+        self.layer.unlink(req, &mut *self.inner.try_borrow_resource()?, path)
+    }
+
+    // File operations
+
+    fn open(&mut self, req: &mut Request, flags: OpenFlags) -> Result<returns::Opened> {
+        self.layer.open(req, &mut *self.inner.try_borrow_resource()?, flags)
+    }
+    fn close(&mut self, req: &mut Request, fh: FileHandle, flags: OpenFlags) -> Result<()> {
+        self.layer.close(req, &mut *self.inner.try_borrow_resource()?, fh, flags)
+    }
+    fn read(&'_ mut self, req: &mut Request, fh: FileHandle, offset: i64, len: u32) -> Result<Cow<'_, [u8]>> {
+        let mut inner = self.inner.try_borrow_resource()?;
+        let data = self.layer.read(req, &mut *inner, fh, offset, len)?;
+        Ok(Cow::Owned(data.into_owned()))
+    }
+
+    // Xattr operations
+
+    fn getxattr(&'_ mut self, req: &mut Request, name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        let mut inner = self.inner.try_borrow_resource()?;
+        let value = self.layer.getxattr(req, &mut *inner, name)?;
+        Ok(Cow::Owned(value.into_owned()))
+    }
+    fn setxattr(&mut self, req: &mut Request, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        self.layer.setxattr(req, &mut *self.inner.try_borrow_resource()?, name, value, flags)
+    }
+    fn listxattr(&mut self, req: &mut Request, output: &mut Vec<OsString>) -> Result<()> {
+        self.layer.listxattr(req, &mut *self.inner.try_borrow_resource()?, output)
+    }
+    fn removexattr(&mut self, req: &mut Request, name: &OsStr) -> Result<()> {
+        self.layer.removexattr(req, &mut *self.inner.try_borrow_resource()?, name)
+    }
+}
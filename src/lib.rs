@@ -26,56 +26,129 @@
 )]
 
 use std::{
-    collections::BTreeMap,
-    convert::TryInto,
+    collections::{BTreeMap, BTreeSet},
+    convert::{TryFrom, TryInto},
     ffi::OsStr,
     fmt,
-    path::Path,
+    io,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path},
+    rc::Rc,
+    time::{Duration, Instant},
 };
 
-use fuse::{
+use fuser::{
     // ReplyBmap,
     // ReplyCreate,
     // ReplyLock,
+    // ReplyLseek,
     // ReplyStatfs,
     // ReplyWrite,
-    // ReplyXattr,
+    consts::{FUSE_DO_READDIRPLUS, FUSE_READDIRPLUS_AUTO},
+    FileAttr,
     Filesystem,
     FileType,
+    KernelConfig,
+    MountOption,
     ReplyAttr,
     ReplyData,
     ReplyDirectory,
+    ReplyDirectoryPlus,
     ReplyEmpty,
     ReplyEntry,
     ReplyOpen,
+    ReplyXattr,
     Request as FuseRequest,
 };
-use log::trace;
+use log::{trace, warn};
 
+pub mod acl;
+pub mod cache;
 pub mod cell;
+pub mod error;
+pub mod layer;
+pub mod metrics;
+pub mod notify;
 pub mod resource;
 pub mod returns;
 
+mod links;
+mod refs;
+mod span;
+mod unwind;
+
+pub use cache::CachePolicy;
 pub use cell::*;
+pub use error::*;
+pub use layer::*;
+pub use metrics::*;
+pub use notify::*;
 pub use resource::*;
 
-/// A result type that defaults to using `c_int` as error
-pub type Result<T, E = libc::c_int> = std::result::Result<T, E>;
+/// A result type that defaults to using `Error` as error
+pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 const ROOT_ID: Inode = Inode(1);
+/// Number of offsets reserved for the `.` and `..` entries, which
+/// are added by `EasyFuse::readdir` before any resource entries
+const DOT_ENTRIES: i64 = 2;
+/// The most entries read from a resource per `readdir`. Entries that
+/// don't fit in the kernel's buffer are simply read again next time.
+const LIST_BATCH: usize = 128;
+/// The preferred I/O size reported for resources that don't choose
+/// their own `blksize`
+const DEFAULT_BLKSIZE: u32 = 4096;
+
+/// An open directory, as seen by the kernel
+#[derive(Debug)]
+struct DirHandle {
+    /// The handle returned from the resource's `opendir`
+    inner: FileHandle,
+    /// All entries at the time of opening, numbered by position, if
+    /// snapshots are enabled
+    snapshot: Option<Rc<[returns::DirEntry]>>,
+}
 
 /// A `Filesystem` implementation that resolves inodes automatically
 /// and uses return values in a more idiomatic way
 pub struct EasyFuse {
     nodes: BTreeMap<Inode, ResourceCell>,
+    parents: BTreeMap<Inode, Inode>,
     next_inode: Inode,
+    snapshot_dirs: bool,
+    dir_handles: BTreeMap<u64, DirHandle>,
+    next_dir_handle: u64,
+    poison_on_panic: bool,
+    poisoned: BTreeSet<Inode>,
+    notifier: Notifier,
+    groups: resource::groups::GroupCache,
+    layers: Vec<Rc<dyn Layer>>,
+    metrics: Metrics,
+    cache_policy: CachePolicy,
+    attr_cache: cache::AttrCache,
+    links: links::LinkIndex,
+    refs: refs::KernelRefs,
 }
 impl Default for EasyFuse {
     fn default() -> Self {
         #[allow(clippy::integer_arithmetic)]
         Self {
             nodes: BTreeMap::new(),
+            parents: BTreeMap::new(),
             next_inode: Inode(ROOT_ID.0 + 1),
+            snapshot_dirs: false,
+            dir_handles: BTreeMap::new(),
+            next_dir_handle: 1,
+            poison_on_panic: false,
+            poisoned: BTreeSet::new(),
+            notifier: Notifier::default(),
+            groups: resource::groups::GroupCache::default(),
+            layers: Vec::new(),
+            metrics: Metrics::default(),
+            cache_policy: CachePolicy::default(),
+            attr_cache: cache::AttrCache::default(),
+            links: links::LinkIndex::default(),
+            refs: refs::KernelRefs::default(),
         }
     }
 }
@@ -89,6 +162,47 @@ impl EasyFuse {
     pub fn new() -> Self {
         Self::default()
     }
+    /// Mount the filesystem on `mountpoint` and serve requests until
+    /// it's unmounted. Unlike mounting with `fuser` directly, this
+    /// connects `notifier` while mounted.
+    pub fn mount<P>(self, mountpoint: P, options: &[MountOption]) -> io::Result<()>
+    where
+        P: AsRef<Path>
+    {
+        let notifier = self.notifier.clone();
+        let mut session = fuser::Session::new(self, mountpoint.as_ref(), options)?;
+        notifier.connect(session.notifier());
+        let result = session.run();
+        notifier.disconnect();
+        result
+    }
+    /// Return a handle that can be used to invalidate the kernel's
+    /// caches, both from resources and from other threads. See
+    /// `Notifier`.
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+    /// Statistics of all operations dispatched so far
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+    /// Mutable access to the statistics, for example to reset them
+    pub fn metrics_mut(&mut self) -> &mut Metrics {
+        &mut self.metrics
+    }
+    /// Insert a read-only file at `path` that shows `metrics` in the
+    /// Prometheus text format, like `insert_path`
+    pub fn insert_stats<P>(&mut self, path: P) -> Result<Inode>
+    where
+        P: AsRef<Path>
+    {
+        let attr = AttrBuilder::file().with_perm(0o444).build();
+        let stats = file::FnFile::new(attr, |req: &mut Request| {
+            Ok(req.fs.metrics().render_prometheus().into_bytes())
+        });
+        self.insert_path(path, FileResource(stats))
+    }
+
     /// Same as `try_register`, but panics on the unlikely case of
     /// integer overflow
     pub fn register<R>(&mut self, resource: R) -> Inode
@@ -113,14 +227,125 @@ impl EasyFuse {
     /// Remove a binding from a certain inode, and return the previous
     /// associated resource, if any
     pub fn unregister(&mut self, inode: Inode) -> Option<ResourceCell> {
+        self.parents.remove(&inode);
+        self.poisoned.remove(&inode);
+        self.metrics.forget(inode);
+        self.refs.clear(inode);
+        self.attr_cache.invalidate(inode);
+        for parent in self.links.forget(inode) {
+            self.attr_cache.invalidate_attr(parent);
+        }
         self.nodes.remove(&inode)
     }
 
-    /// Resolve an inode to a resource
+    /// Resolve an inode to a resource, wrapped in all layers added
+    /// with `add_layer`
     pub fn resolve(&mut self, inode: Inode) -> Option<ResourceCell> {
+        let mut node = self.nodes.get(&inode).cloned()?;
+        for layer in &self.layers {
+            node = ResourceCell::from(layer::Layered {
+                layer: Rc::clone(layer),
+                inner: node,
+            });
+        }
+        Some(node)
+    }
+    /// Resolve an inode to the resource that was registered, without
+    /// any layers
+    pub fn resolve_unlayered(&self, inode: Inode) -> Option<ResourceCell> {
         self.nodes.get(&inode).cloned()
     }
 
+    /// Wrap every resource in `layer` when it's resolved. Layers
+    /// added later wrap the ones added before them, so they're called
+    /// first. See the `layer` module.
+    pub fn add_layer<L>(&mut self, layer: L)
+    where
+        L: Layer + 'static
+    {
+        self.layers.push(Rc::new(layer));
+    }
+
+    /// Record `parent` as the directory containing `inode`, which is
+    /// what the `..` entry of `inode` will point to. This is done
+    /// automatically whenever the kernel looks up or lists a child,
+    /// so you only need it for directories that are read before ever
+    /// being looked up.
+    pub fn set_parent(&mut self, inode: Inode, parent: Inode) {
+        if inode != ROOT_ID && inode != parent {
+            self.parents.insert(inode, parent);
+        }
+    }
+    /// Return the directory containing `inode`, if known. The root
+    /// is always its own parent.
+    pub fn parent(&self, inode: Inode) -> Option<Inode> {
+        if inode == ROOT_ID {
+            Some(ROOT_ID)
+        } else {
+            self.parents.get(&inode).copied()
+        }
+    }
+
+    /// Choose whether to take a snapshot of a directory's entries
+    /// when it's opened, and list from that snapshot until it's
+    /// closed again. This guarantees that entries don't shift around
+    /// when a directory changes while being listed, at the cost of
+    /// reading the whole directory up front. Disabled by default.
+    pub fn set_snapshot_dirs(&mut self, enabled: bool) {
+        self.snapshot_dirs = enabled;
+    }
+
+    /// Choose how long attributes and entries may be cached, and
+    /// forget everything cached under the previous policy. See
+    /// `CachePolicy`.
+    pub fn set_cache_policy(&mut self, policy: CachePolicy) {
+        self.cache_policy = policy;
+        self.attr_cache.clear();
+    }
+    /// The current cache policy
+    pub fn cache_policy(&self) -> &CachePolicy {
+        &self.cache_policy
+    }
+    /// Mutable access to the cache policy, for example to override
+    /// the TTL of a single inode. This only affects attributes and
+    /// entries returned after the change.
+    pub fn cache_policy_mut(&mut self) -> &mut CachePolicy {
+        &mut self.cache_policy
+    }
+    /// Forget the cached attributes of `inode`, along with all cached
+    /// entries in it or pointing to it, so the resource is asked
+    /// again next time. This only affects the cache in `EasyFuse`,
+    /// the kernel's cache can be invalidated with `notifier`.
+    pub fn invalidate_cache(&mut self, inode: Inode) {
+        self.attr_cache.invalidate(inode);
+    }
+
+    /// Choose whether a resource that panics should be poisoned, which
+    /// makes every following operation on its inode fail with `EIO`
+    /// without calling into it. Either way, the panic itself is
+    /// caught and replied to with `EIO`. Disabled by default.
+    pub fn set_poison_on_panic(&mut self, enabled: bool) {
+        self.poison_on_panic = enabled;
+    }
+    /// Returns true if the resource at `inode` panicked and has been
+    /// poisoned, see `set_poison_on_panic`
+    pub fn is_poisoned(&self, inode: Inode) -> bool {
+        self.poisoned.contains(&inode)
+    }
+    /// Make a poisoned inode usable again. Returns false if it wasn't
+    /// poisoned.
+    pub fn clear_poison(&mut self, inode: Inode) -> bool {
+        self.poisoned.remove(&inode)
+    }
+    /// Poison `inode` after it panicked in `operation`, if enabled
+    pub(crate) fn poison(&mut self, inode: Inode, operation: &str) {
+        if self.poison_on_panic {
+            warn!("poisoning inode {} after panic in {}", inode.0, operation);
+            self.poisoned.insert(inode);
+            self.attr_cache.invalidate(inode);
+        }
+    }
+
     /// Bind a resource to a hardcoded root inode ID
     pub fn set_root<R>(&mut self, resource: R) -> Option<ResourceCell>
     where
@@ -129,21 +354,409 @@ impl EasyFuse {
         self.nodes.insert(ROOT_ID, resource.into())
     }
 
+    /// Walk `path` from the root resource using each directory's
+    /// `lookup`, and return the inode and resource it points to. The
+    /// path is always interpreted relative to the root.
+    pub fn resolve_path<P>(&mut self, path: P) -> Result<(Inode, ResourceCell)>
+    where
+        P: AsRef<Path>
+    {
+        let mut stack = vec![ROOT_ID];
+        for component in path.as_ref().components() {
+            match component {
+                Component::Prefix(_) => return Err(Error::Errno(libc::EINVAL)),
+                Component::RootDir | Component::CurDir => (),
+                Component::ParentDir => if stack.len() > 1 {
+                    stack.pop();
+                },
+                Component::Normal(name) => {
+                    let parent = *stack.last().expect("path stack is never empty");
+                    let child = self.lookup_child(parent, name)?;
+                    stack.push(child);
+                },
+            }
+        }
+        let inode = *stack.last().expect("path stack is never empty");
+        let resource = self.resolve(inode).ok_or(Error::NotFound)?;
+        Ok((inode, resource))
+    }
+    /// Register a resource and bind it to `path`, creating any
+    /// missing parent directories as `StaticDirectory` instances on
+    /// the way. Fails with `EEXIST` if the path is already taken. As
+    /// long as the resource is only bound elsewhere through
+    /// `link_path` or the kernel, it's unregistered once it has been
+    /// unlinked everywhere and the kernel is done with it.
+    pub fn insert_path<P, R>(&mut self, path: P, resource: R) -> Result<Inode>
+    where
+        P: AsRef<Path>,
+        R: Into<ResourceCell>
+    {
+        let path = path.as_ref();
+        let name = path.file_name().ok_or(Error::Errno(libc::EINVAL))?;
+        let parent = self.create_dirs(path.parent().unwrap_or_else(|| Path::new("")))?;
+
+        match self.lookup_child(parent, name) {
+            Ok(_) => return Err(Error::Errno(libc::EEXIST)),
+            Err(ref err) if err.errno() == libc::ENOENT => (),
+            Err(err) => return Err(err),
+        }
+        let inode = self.try_register(resource).ok_or(Error::Errno(libc::ENOSPC))?;
+        if let Err(err) = self.link_child(parent, name, inode) {
+            self.unregister(inode);
+            return Err(err);
+        }
+        self.links.complete(inode);
+        Ok(inode)
+    }
+    /// Bind an already registered `inode` to `path` as well, like a
+    /// hard link, creating any missing parent directories like
+    /// `insert_path`. Fails with `EEXIST` if the path is already
+    /// taken.
+    pub fn link_path<P>(&mut self, inode: Inode, path: P) -> Result<()>
+    where
+        P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let name = path.file_name().ok_or(Error::Errno(libc::EINVAL))?;
+        let parent = self.create_dirs(path.parent().unwrap_or_else(|| Path::new("")))?;
+
+        if !self.nodes.contains_key(&inode) {
+            return Err(Error::NotFound);
+        }
+        self.link_child(parent, name, inode)
+    }
+    /// Unbind `path` from its parent directory, returning the
+    /// resource it pointed to. If the resource was inserted with
+    /// `insert_path` and this was its last name, it's also
+    /// unregistered, once the kernel has no open handles or entries
+    /// for it left. Anything else stays registered, as it may still
+    /// be bound somewhere `EasyFuse` doesn't know about.
+    pub fn remove_path<P>(&mut self, path: P) -> Result<ResourceCell>
+    where
+        P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let name = path.file_name().ok_or(Error::Errno(libc::EINVAL))?;
+        let (parent, node) = self.resolve_path(path.parent().unwrap_or_else(|| Path::new("")))?;
+
+        // Look it up first, so this binding is known even if it was
+        // never seen before
+        self.lookup_child(parent, name)?;
+        let inode = node.try_borrow_resource()?.unlink(&mut self.internal_request(parent), name)?;
+        let node = self.resolve_unlayered(inode).ok_or(Error::NotFound)?;
+        self.unlinked(parent, name, inode);
+        Ok(node)
+    }
+    /// The number of names `inode` is known to be bound to. Bindings
+    /// are learned whenever a directory is looked into or listed,
+    /// either by the kernel or by path functions like `insert_path`,
+    /// so this may miss bindings in directories that haven't been
+    /// visited yet.
+    pub fn links(&self, inode: Inode) -> u32 {
+        self.links.links(inode)
+    }
+
+    fn lookup_child(&mut self, parent: Inode, name: &OsStr) -> Result<Inode> {
+        let node = self.resolve(parent).ok_or(Error::NotFound)?;
+        let entry = node.try_borrow_resource()?.lookup(&mut self.internal_request(parent), name)?;
+        let child = Inode(entry.attr.inner.ino);
+        self.set_parent(child, parent);
+        self.bind(parent, name, child, entry.attr.inner.kind);
+        Ok(child)
+    }
+    fn link_child(&mut self, parent: Inode, name: &OsStr, inode: Inode) -> Result<()> {
+        let node = self.resolve(parent).ok_or(Error::NotFound)?;
+        node.try_borrow_resource()?.link(&mut self.internal_request(parent), name, inode)?;
+        self.attr_cache.invalidate(inode);
+        // Look it up again, to record the new binding
+        self.lookup_child(parent, name).map(drop)
+    }
+    /// Record that `name` in `parent` is bound to `inode`, forgetting
+    /// any cached attributes whose link count this changes
+    fn bind(&mut self, parent: Inode, name: &OsStr, inode: Inode, kind: FileType) {
+        if self.links.bind(parent, name, inode, kind) {
+            self.attr_cache.invalidate_attr(inode);
+            self.attr_cache.invalidate_attr(parent);
+        }
+    }
+    /// Forget that `name` in `parent` is bound, see `bind`
+    fn unbind(&mut self, parent: Inode, name: &OsStr) {
+        if let Some(inode) = self.links.unbind(parent, name) {
+            self.attr_cache.invalidate_attr(inode);
+            self.attr_cache.invalidate_attr(parent);
+        }
+    }
+    /// Clean up after `name` in `parent` was unlinked from `inode`,
+    /// unregistering `inode` if that was its last binding, see
+    /// `remove_path`
+    fn unlinked(&mut self, parent: Inode, name: &OsStr, inode: Inode) {
+        self.attr_cache.invalidate_entry(parent, name);
+        self.unbind(parent, name);
+        if self.links.orphan(inode) {
+            self.reap(inode);
+        }
+    }
+    /// Unregister `inode` if it's an orphan the kernel no longer
+    /// refers to
+    fn reap(&mut self, inode: Inode) {
+        if self.links.is_orphan(inode) && !self.refs.is_referenced(inode) {
+            self.unregister(inode);
+        }
+    }
+    fn create_dirs(&mut self, path: &Path) -> Result<Inode> {
+        let mut current = ROOT_ID;
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir | Component::CurDir => continue,
+                Component::Normal(name) => name,
+                Component::Prefix(_) | Component::ParentDir => return Err(Error::Errno(libc::EINVAL)),
+            };
+            current = match self.lookup_child(current, name) {
+                Ok(child) => child,
+                Err(ref err) if err.errno() == libc::ENOENT => {
+                    let dir = dir::StaticDirectory::new(AttrBuilder::directory().build());
+                    let child = self.try_register(dir).ok_or(Error::Errno(libc::ENOSPC))?;
+                    if let Err(err) = self.link_child(current, name, child) {
+                        self.unregister(child);
+                        return Err(err);
+                    }
+                    child
+                },
+                Err(err) => return Err(err),
+            };
+        }
+        Ok(current)
+    }
+
     fn request<'a>(&'a mut self, inode: Inode, req: &'a FuseRequest) -> Request<'a> {
+        span::record("pid", req.pid());
+        span::record("uid", req.uid());
+        Request {
+            inner: Some(req),
+            fs: self,
+            inode,
+            acl: None,
+            checking: false,
+        }
+    }
+    /// Like `request`, but with the access ACL of `resource` attached,
+    /// for operations that may check access. See `Request::access_acl`.
+    fn checked_request<'a>(&'a mut self, inode: Inode, req: &'a FuseRequest, resource: &mut dyn Resource) -> Result<Request<'a>> {
+        let mut req = self.request(inode, req);
+        req.acl = req.check_permissions(|req| acl::access_acl(resource, req))?;
+        Ok(req)
+    }
+    fn internal_request(&mut self, inode: Inode) -> Request<'_> {
         Request {
-            inner: req,
+            inner: None,
             fs: self,
             inode,
+            acl: None,
+            checking: false,
+        }
+    }
+
+    /// Read the next entries of the directory at `ino` from the
+    /// kernel's `offset`, along with the kernel offset of each. This
+    /// adds the dot entries and shifts resource cookies past them. At
+    /// most `LIST_BATCH` entries are read at once.
+    fn list_dir(&mut self, operation: &'static str, req: &FuseRequest<'_>, ino: Inode, fh: u64, offset: i64) -> Result<Vec<(returns::DirEntry, i64)>> {
+        self.list_dir_with(operation, ino, fh, offset, |fs, node, inner, cursor, output| {
+            let mut resource = node.try_borrow_resource()?;
+            let mut req = fs.checked_request(ino, req, &mut *resource)?;
+            resource.readdir_from(&mut req, inner, cursor, output)
+        })
+    }
+    /// See `list_dir`. Unless the handle has a snapshot, the entries
+    /// of the resource are read through `read`, from its own `cursor`.
+    fn list_dir_with<F>(&mut self, operation: &'static str, ino: Inode, fh: u64, offset: i64, read: F) -> Result<Vec<(returns::DirEntry, i64)>>
+    where
+        F: FnOnce(&mut Self, &ResourceCell, FileHandle, i64, &mut returns::DirWriter) -> Result<()>
+    {
+        let handle = self.dir_handles.get(&fh).ok_or(Error::Errno(libc::EBADF))?;
+        let (inner, snapshot) = (handle.inner, handle.snapshot.clone());
+        let parent = self.parent(ino).unwrap_or(ino);
+
+        let dots = vec![
+            returns::DirEntry::new(ino, FileType::Directory, OsStr::new(".")).with_offset(1),
+            returns::DirEntry::new(parent, FileType::Directory, OsStr::new("..")).with_offset(DOT_ENTRIES),
+        ];
+        let mut entries: Vec<_> = dots.into_iter()
+            .filter(|dot| offset < dot.offset)
+            .map(|dot| {
+                let offset = dot.offset;
+                (dot, offset)
+            })
+            .collect();
+        let dot_count = entries.len();
+
+        let cursor = offset.saturating_sub(DOT_ENTRIES).max(0);
+        self.dispatch(operation, ino, |fs, node| {
+            span::record("offset", offset);
+            // An offset of zero would make the kernel restart the
+            // listing from the top, forever
+            let mut invalid = false;
+            let mut sink = |entry: &returns::DirEntry| {
+                if entry.offset <= 0 {
+                    invalid = true;
+                    return false;
+                }
+                let offset = match entry.offset.checked_add(DOT_ENTRIES) {
+                    Some(offset) => offset,
+                    None => return false,
+                };
+                if entries.len() >= LIST_BATCH {
+                    return false;
+                }
+                entries.push((entry.clone(), offset));
+                true
+            };
+            let mut output = returns::DirWriter::new(&mut sink);
+
+            let result = if let Some(snapshot) = snapshot {
+                let start = usize::try_from(cursor).unwrap_or(0);
+                for entry in snapshot.iter().skip(start) {
+                    if !output.push(entry.clone()) {
+                        break;
+                    }
+                }
+                Ok(())
+            } else {
+                read(fs, node, inner, cursor, &mut output)
+            };
+            if invalid {
+                warn!("{} on inode {} returned an entry with a non-positive offset", operation, ino.0);
+                return Err(Error::Errno(libc::EIO));
+            }
+            result
+        })?;
+
+        for (entry, _) in &entries[dot_count..] {
+            self.set_parent(entry.inode, ino);
+            self.bind(ino, &entry.name, entry.inode, entry.filetype);
+        }
+        Ok(entries)
+    }
+
+    /// Resolve `inode` and run `f` on it, catching any panic and
+    /// converting it to `EIO`. With the `tracing` feature, this is
+    /// all done inside a span for the operation.
+    fn dispatch<T, F>(&mut self, operation: &'static str, inode: Inode, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self, &ResourceCell) -> Result<T>
+    {
+        let start = Instant::now();
+        let span = span::OpSpan::new(operation, inode);
+        let result = span.in_scope(|| {
+            if self.poisoned.contains(&inode) {
+                return Err(Error::Errno(libc::EIO));
+            }
+            let node = self.resolve(inode).ok_or(Error::NotFound)?;
+            if let Ok(resource) = node.try_borrow() {
+                span::record("resource", resource.type_name());
+            }
+            match unwind::catch(operation, || f(self, &node)) {
+                Some(result) => result,
+                None => {
+                    self.poison(inode, operation);
+                    Err(Error::Errno(libc::EIO))
+                },
+            }
+        });
+        self.metrics.record(operation, inode, &result, start.elapsed());
+        span.finish(&result);
+        result
+    }
+}
+
+impl EasyFuse {
+    /// The attributes of `ino`, either cached or from its resource,
+    /// along with the TTL to reply with
+    fn attr(&mut self, req: &FuseRequest<'_>, ino: Inode) -> Result<(returns::Attr, Duration)> {
+        self.apply_invalidations();
+        if let Some(cached) = self.attr_cache.attr(ino) {
+            self.metrics.record_cached("getattr");
+            return Ok(cached);
+        }
+        let attr = self.dispatch("getattr", ino, |fs, node| {
+            node.try_borrow_resource()?.getattr(&mut fs.request(ino, req))
+        })?;
+        Ok(self.finish_attr(ino, attr))
+    }
+    /// Fill in what the resource left out of the attributes of `ino`
+    /// and cache them, returning them along with the TTL to reply with
+    fn finish_attr(&mut self, ino: Inode, mut attr: returns::Attr) -> (returns::Attr, Duration) {
+        attr.inner.ino = ino.0;
+        complete_attr(&self.links, ino, &mut attr.inner);
+        let ttl = self.attr_ttl(ino, &attr);
+        self.attr_cache.insert_attr(ino, attr, ttl);
+        (attr, ttl)
+    }
+    /// Drop whatever `Notifier` invalidated from the cache, before it's
+    /// used to answer anything
+    fn apply_invalidations(&mut self) {
+        for invalidation in self.notifier.take_pending() {
+            match invalidation {
+                Invalidation::Inode(inode) => self.attr_cache.invalidate_attr(inode),
+                Invalidation::Entry(parent, name) => self.attr_cache.invalidate_entry(parent, &name),
+            }
+        }
+    }
+    /// The TTL to reply with for `attr`, which is the resource's own
+    /// TTL unless it's zero
+    fn attr_ttl(&self, inode: Inode, attr: &returns::Attr) -> Duration {
+        if attr.ttl > Duration::from_secs(0) {
+            attr.ttl
+        } else {
+            self.cache_policy.ttl(inode, attr.inner.kind)
         }
     }
 }
 
+/// Fill in the link count, block count and block size of `attr`, if
+/// the resource left them as zero
+fn complete_attr(links: &links::LinkIndex, inode: Inode, attr: &mut FileAttr) {
+    if attr.nlink == 0 {
+        attr.nlink = links.nlink(inode, attr.kind);
+    }
+    if attr.blocks == 0 {
+        attr.blocks = blocks_for(attr.size);
+    }
+    if attr.blksize == 0 {
+        attr.blksize = DEFAULT_BLKSIZE;
+    }
+}
+/// Make sure the caller may add and remove names in the directory
+/// `resource`, see `EasyFuse::checked_request`
+fn ensure_writable(resource: &mut dyn Resource, req: &mut Request) -> Result<()> {
+    let attr = req.check_permissions(|req| resource.getattr(req))?;
+    req.ensure_access(&attr.inner, Permissions::WRITE | Permissions::EXECUTE)
+}
+/// Convert `open` flags to how they are passed to resources. FUSE
+/// passes them as an `i32`, but they're just a bit pattern.
+#[allow(clippy::cast_sign_loss)]
+fn open_flags(flags: i32) -> u32 {
+    flags as u32
+}
+
+/// Reply to `getxattr` or `listxattr`. The kernel first asks with a
+/// `size` of zero to find out how big of a buffer it needs.
+fn reply_xattr(reply: ReplyXattr, size: u32, value: &[u8]) {
+    match u32::try_from(value.len()) {
+        Err(_) => reply.error(libc::E2BIG),
+        Ok(len) if size == 0 => reply.size(len),
+        Ok(len) if len > size => reply.error(libc::ERANGE),
+        Ok(_) => reply.data(value),
+    }
+}
+
 macro_rules! attempt {
     ($reply:expr, $result:expr) => {
         match $result {
             Ok(ok) => ok,
             Err(err) => {
-                $reply.error(err);
+                err.log();
+                $reply.error(err.errno());
                 return;
             },
         }
@@ -151,15 +764,23 @@ macro_rules! attempt {
 }
 
 impl Filesystem for EasyFuse {
-    fn getattr(&mut self, req: &FuseRequest, ino: u64, reply: ReplyAttr) {
-        let ino = Inode(ino);
-        let node = attempt!(reply, self.resolve(ino).ok_or(libc::ENOENT));
+    fn init(&mut self, _req: &FuseRequest<'_>, config: &mut KernelConfig) -> Result<(), libc::c_int> {
+        // Let the kernel choose between readdir and readdirplus. This
+        // fails on kernels without readdirplus, which is fine.
+        let _ = config.add_capabilities(FUSE_DO_READDIRPLUS | FUSE_READDIRPLUS_AUTO);
+        Ok(())
+    }
 
-        let result = node.borrow_mut().getattr(&mut self.request(ino, req));
+    fn getattr(&mut self, req: &FuseRequest<'_>, ino: u64, reply: ReplyAttr) {
+        let result = self.attr(req, Inode(ino));
         trace!("getattr(...) = {:#?}", result);
-        let mut attr = attempt!(reply, result);
-        attr.inner.ino = ino.0;
-        reply.attr(&attr.ttl, &attr.inner);
+        let (attr, ttl) = attempt!(reply, result);
+        reply.attr(&ttl, &attr.inner);
+    }
+    fn forget(&mut self, _req: &FuseRequest<'_>, ino: u64, nlookup: u64) {
+        let ino = Inode(ino);
+        self.refs.forget(ino, nlookup);
+        self.reap(ino);
     }
 
     //  ____  _                                   _   _
@@ -169,44 +790,191 @@ impl Filesystem for EasyFuse {
     // |____/|_|_|     \___/| .__/ \___|_|  \__,_|\__|_|\___/|_| |_|___/
     //                      |_|
 
-    fn lookup(&mut self, req: &FuseRequest, parent: u64, name: &OsStr, reply: ReplyEntry) {
+    fn lookup(&mut self, req: &FuseRequest<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
         let parent = Inode(parent);
-        let node = attempt!(reply, self.resolve(parent).ok_or(libc::ENOENT));
+        self.apply_invalidations();
+        if let Some((entry, ttl)) = self.attr_cache.entry(parent, name) {
+            trace!("lookup(...) = cached {:#?}", entry);
+            self.metrics.record_cached("lookup");
+            self.refs.lookup(Inode(entry.attr.inner.ino));
+            reply.entry(&ttl, &entry.attr.inner, entry.generation);
+            return;
+        }
 
-        let result = node.borrow_mut().lookup(&mut self.request(parent, req), name);
+        let result = self.dispatch("lookup", parent, |fs, node| {
+            let mut resource = node.try_borrow_resource()?;
+            let mut req = fs.checked_request(parent, req, &mut *resource)?;
+            resource.lookup(&mut req, name)
+        });
         trace!("lookup(...) = {:#?}", result);
-        let entry = attempt!(reply, result);
-        reply.entry(&entry.attr.ttl, &entry.attr.inner, entry.generation);
+        let mut entry = attempt!(reply, result);
+        let child = Inode(entry.attr.inner.ino);
+        self.set_parent(child, parent);
+        self.bind(parent, name, child, entry.attr.inner.kind);
+        complete_attr(&self.links, child, &mut entry.attr.inner);
+
+        let ttl = self.attr_ttl(child, &entry.attr);
+        self.attr_cache.insert_entry(parent, name, entry, ttl);
+        self.refs.lookup(child);
+        reply.entry(&ttl, &entry.attr.inner, entry.generation);
     }
-    fn readdir(&mut self, req: &FuseRequest, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+    fn opendir(&mut self, req: &FuseRequest<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
         let ino = Inode(ino);
-        let node = attempt!(reply, self.resolve(ino).ok_or(libc::ENOENT));
-        let mut entries = vec![
-            returns::DirEntry::new(ino, FileType::Directory, OsStr::new(".")),
-            returns::DirEntry::new(ino, FileType::Directory, OsStr::new("..")),
-        ];
+        let flags = open_flags(flags);
+        let result = self.dispatch("opendir", ino, |fs, node| {
+            let inner = {
+                let mut resource = node.try_borrow_resource()?;
+                let mut req = fs.checked_request(ino, req, &mut *resource)?;
+                resource.opendir(&mut req, flags)?
+            };
+            if !fs.snapshot_dirs {
+                return Ok((inner, None));
+            }
 
-        let result = node.borrow_mut().readdir(&mut self.request(ino, req), &mut entries);
-        trace!("readdir(...) = {:?}", result);
+            let mut snapshot = Vec::new();
+            let result = node.try_borrow_resource()?.readdir_from(
+                &mut fs.request(ino, req),
+                inner,
+                0,
+                &mut returns::DirWriter::new(&mut |entry| {
+                    snapshot.push(entry.clone());
+                    true
+                })
+            );
+            if let Err(err) = result {
+                if let Ok(mut node) = node.try_borrow_resource() {
+                    let _ = node.releasedir(&mut fs.request(ino, req), inner, flags);
+                }
+                return Err(err);
+            }
+            let mut offset = 0_i64;
+            for entry in &mut snapshot {
+                offset = offset.checked_add(1).ok_or(Error::Errno(libc::EOVERFLOW))?;
+                entry.offset = offset;
+            }
+            Ok((inner, Some(Rc::from(snapshot))))
+        });
+        trace!("opendir(...) = {:?}", result);
+        let (inner, snapshot) = attempt!(reply, result);
+
+        let fh = self.next_dir_handle;
+        self.next_dir_handle = attempt!(reply, fh.checked_add(1).ok_or(Error::Errno(libc::ENFILE)));
+        self.dir_handles.insert(fh, DirHandle { inner, snapshot });
+        self.refs.open(ino);
+        reply.opened(fh, 0);
+    }
+    fn releasedir(&mut self, req: &FuseRequest<'_>, ino: u64, fh: u64, flags: i32, reply: ReplyEmpty) {
+        let ino = Inode(ino);
+        let flags = open_flags(flags);
+        let handle = attempt!(reply, self.dir_handles.remove(&fh).ok_or(Error::Errno(libc::EBADF)));
+        self.refs.release(ino);
+
+        let result = self.dispatch("releasedir", ino, |fs, node| {
+            node.try_borrow_resource()?.releasedir(&mut fs.request(ino, req), handle.inner, flags)
+        });
+        trace!("releasedir(...) = {:?}", result);
+        self.reap(ino);
         attempt!(reply, result);
 
-        let mut i = 1;
-        for entry in entries.into_iter().skip(offset.try_into().unwrap_or(0)) {
-            reply.add(entry.inode.0, i, entry.filetype, &entry.name);
-            i = i.checked_add(1).expect("integer overflow");
+        reply.ok();
+    }
+    fn readdir(&mut self, req: &FuseRequest<'_>, ino: u64, fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let result = self.list_dir("readdir", req, Inode(ino), fh, offset);
+        trace!("readdir(...) = {:?}", result);
+        let entries = attempt!(reply, result);
+        for (entry, offset) in &entries {
+            if reply.add(entry.inode.0, *offset, entry.filetype, &entry.name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+    fn readdirplus(&mut self, req: &FuseRequest<'_>, ino: u64, fh: u64, offset: i64, mut reply: ReplyDirectoryPlus) {
+        let result = self.list_dir("readdirplus", req, Inode(ino), fh, offset);
+        trace!("readdirplus(...) = {:?}", result);
+        let entries = attempt!(reply, result);
+        for (i, (entry, offset)) in entries.into_iter().enumerate() {
+            // The kernel takes the inode from the attributes, so every
+            // entry needs them, like for a lookup
+            let result = match entry.attr {
+                Some(attr) => Ok(self.finish_attr(entry.inode, attr)),
+                None => self.attr(req, entry.inode),
+            };
+            let (attr, ttl) = match result {
+                Ok(attr) => attr,
+                // Send what fits so far, and fail once the kernel
+                // asks for the rest
+                Err(_) if i > 0 => break,
+                Err(err) => {
+                    err.log();
+                    reply.error(err.errno());
+                    return;
+                },
+            };
+            if reply.add(entry.inode.0, offset, &entry.name, &ttl, &attr.inner, 0) {
+                break;
+            }
+            // The kernel counts these like lookups, except for the
+            // dot entries
+            if entry.name != OsStr::new(".") && entry.name != OsStr::new("..") {
+                self.refs.lookup(entry.inode);
+            }
         }
         reply.ok();
     }
-    fn symlink(&mut self, req: &FuseRequest, parent: u64, name: &OsStr, link: &Path, reply: ReplyEntry) {
+    fn symlink(&mut self, req: &FuseRequest<'_>, parent: u64, name: &OsStr, link: &Path, reply: ReplyEntry) {
         let parent = Inode(parent);
-        let node = attempt!(reply, self.resolve(parent).ok_or(libc::ENOENT));
-
-        let result = node.borrow_mut().symlink(&mut self.request(parent, req), name, link);
+        let result = self.dispatch("symlink", parent, |fs, node| {
+            let mut resource = node.try_borrow_resource()?;
+            let mut req = fs.checked_request(parent, req, &mut *resource)?;
+            resource.symlink(&mut req, name, link)
+        });
         trace!("symlink(...) = {:#?}", result);
         let mut entry = attempt!(reply, result);
+        let child = Inode(entry.attr.inner.ino);
+        self.set_parent(child, parent);
+        self.bind(parent, name, child, entry.attr.inner.kind);
+        complete_attr(&self.links, child, &mut entry.attr.inner);
+
+        let ttl = self.attr_ttl(child, &entry.attr);
+        self.attr_cache.insert_entry(parent, name, entry, ttl);
+        self.refs.lookup(child);
+        reply.entry(&ttl, &entry.attr.inner, entry.generation);
+    }
+    fn link(&mut self, req: &FuseRequest<'_>, ino: u64, newparent: u64, newname: &OsStr, reply: ReplyEntry) {
+        let (ino, parent) = (Inode(ino), Inode(newparent));
+        let result = self.dispatch("link", parent, |fs, node| {
+            let mut resource = node.try_borrow_resource()?;
+            let mut req = fs.checked_request(parent, req, &mut *resource)?;
+            ensure_writable(&mut *resource, &mut req)?;
+            resource.link(&mut req, newname, ino)?;
+            resource.lookup(&mut req, newname)
+        });
+        trace!("link(...) = {:#?}", result);
+        let mut entry = attempt!(reply, result);
+        let child = Inode(entry.attr.inner.ino);
+        self.set_parent(child, parent);
+        self.bind(parent, newname, child, entry.attr.inner.kind);
+        complete_attr(&self.links, child, &mut entry.attr.inner);
+
+        let ttl = self.attr_ttl(child, &entry.attr);
+        self.attr_cache.insert_entry(parent, newname, entry, ttl);
+        self.refs.lookup(child);
+        reply.entry(&ttl, &entry.attr.inner, entry.generation);
+    }
+    fn unlink(&mut self, req: &FuseRequest<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let parent = Inode(parent);
+        let result = self.dispatch("unlink", parent, |fs, node| {
+            let mut resource = node.try_borrow_resource()?;
+            let mut req = fs.checked_request(parent, req, &mut *resource)?;
+            ensure_writable(&mut *resource, &mut req)?;
+            resource.unlink(&mut req, name)
+        });
+        trace!("unlink(...) = {:?}", result);
+        let inode = attempt!(reply, result);
 
-        entry.attr.inner.ino = parent.0;
-        reply.entry(&entry.attr.ttl, &entry.attr.inner, entry.generation);
+        self.unlinked(parent, name, inode);
+        reply.ok();
     }
 
     //  _____ _ _                                   _   _
@@ -216,42 +984,126 @@ impl Filesystem for EasyFuse {
     // |_|   |_|_|\___|  \___/| .__/ \___|_|  \__,_|\__|_|\___/|_| |_|___/
     //                        |_|
 
-    fn open(&mut self, req: &FuseRequest, ino: u64, flags: u32, reply: ReplyOpen) {
+    fn open(&mut self, req: &FuseRequest<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
         let ino = Inode(ino);
-        let node = attempt!(reply, self.resolve(ino).ok_or(libc::ENOENT));
-
-        let result = node.borrow_mut().open(&mut self.request(ino, req), flags);
+        let flags = OpenFlags::from_bits_truncate(open_flags(flags));
+        let result = self.dispatch("open", ino, |fs, node| {
+            let mut resource = node.try_borrow_resource()?;
+            let mut req = fs.checked_request(ino, req, &mut *resource)?;
+            let attr = req.check_permissions(|req| resource.getattr(req))?;
+            req.ensure_access(&attr.inner, flags.access().permissions())?;
+            resource.open(&mut req, flags)
+        });
         trace!("open(...) = {:?}", result);
-        let handle = attempt!(reply, result);
+        let opened = attempt!(reply, result);
 
-        reply.opened(handle.0, 0);
+        self.refs.open(ino);
+        reply.opened(opened.handle.0, opened.flags.bits());
     }
-    fn release(&mut self, req: &FuseRequest, ino: u64, fh: u64, flags: u32, _lock_owner: u64, _flush: bool, reply: ReplyEmpty) {
+    fn release(&mut self, req: &FuseRequest<'_>, ino: u64, fh: u64, flags: i32, _lock_owner: Option<u64>, _flush: bool, reply: ReplyEmpty) {
         let ino = Inode(ino);
-        let node = attempt!(reply, self.resolve(ino).ok_or(libc::ENOENT));
-
-        let result = node.borrow_mut().close(&mut self.request(ino, req), FileHandle(fh), flags);
+        let flags = OpenFlags::from_bits_truncate(open_flags(flags));
+        let result = self.dispatch("close", ino, |fs, node| {
+            node.try_borrow_resource()?.close(&mut fs.request(ino, req), FileHandle(fh), flags)
+        });
         trace!("close(...) = {:?}", result);
+        self.refs.release(ino);
+        self.reap(ino);
         attempt!(reply, result);
 
         reply.ok();
     }
-    fn read(&mut self, req: &FuseRequest, ino: u64, fh: u64, offset: i64, len: u32, reply: ReplyData) {
+    fn read(&mut self, req: &FuseRequest<'_>, ino: u64, fh: u64, offset: i64, len: u32, _flags: i32, _lock_owner: Option<u64>, reply: ReplyData) {
         let ino = Inode(ino);
-        let node = attempt!(reply, self.resolve(ino).ok_or(libc::ENOENT));
-        {
-            let mut node = node.borrow_mut();
+        // The data is borrowed from the resource, so the reply has to
+        // be sent from inside. Keep it around to report errors, though.
+        let mut reply = Some(reply);
+        let result = self.dispatch("read", ino, |fs, node| {
+            let mut node = node.try_borrow_resource()?;
 
-            let result = node.read(&mut self.request(ino, req), FileHandle(fh), offset, len);
+            span::record("offset", offset);
+            span::record("len", len);
+            let mut req = fs.checked_request(ino, req, &mut *node)?;
+            let result = node.read(&mut req, FileHandle(fh), offset, len);
             trace!("read(...) = {:?}", result);
-            let buf = attempt!(reply, result);
+            let buf = result?;
+            span::record("bytes", u64::try_from(buf.len()).unwrap_or(u64::max_value()));
+            fs.metrics.add_bytes("read", buf.len());
 
             assert!(
                 buf.len() <= len.try_into().unwrap_or(usize::max_value()),
                 "Number of read bytes should never exceed numbers of requested bytes"
             );
-            reply.data(&buf);
+            if let Some(reply) = reply.take() {
+                reply.data(&buf);
+            }
+            Ok(())
+        });
+        if let Some(reply) = reply {
+            attempt!(reply, result);
+        }
+    }
+
+    // __  __     _   _                                     _   _
+    // \ \/ /__ _| |_| |_ _ __    ___  _ __   ___ _ __ __ _| |_(_) ___  _ __  ___
+    //  \  // _` | __| __| '__|  / _ \| '_ \ / _ \ '__/ _` | __| |/ _ \| '_ \/ __|
+    //  /  \ (_| | |_| |_| |    | (_) | |_) |  __/ | | (_| | |_| | (_) | | | \__ \
+    // /_/\_\__,_|\__|\__|_|     \___/| .__/ \___|_|  \__,_|\__|_|\___/|_| |_|___/
+    //                                |_|
+
+    fn getxattr(&mut self, req: &FuseRequest<'_>, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let ino = Inode(ino);
+        let result = self.dispatch("getxattr", ino, |fs, node| {
+            let mut node = node.try_borrow_resource()?;
+            let value = node.getxattr(&mut fs.request(ino, req), name)?;
+            Ok(value.into_owned())
+        });
+        trace!("getxattr(...) = {:?}", result);
+        let value = attempt!(reply, result);
+
+        reply_xattr(reply, size, &value);
+    }
+    fn setxattr(&mut self, req: &FuseRequest<'_>, ino: u64, name: &OsStr, value: &[u8], flags: i32, _position: u32, reply: ReplyEmpty) {
+        let ino = Inode(ino);
+        let flags = XattrFlags::from_bits_truncate(open_flags(flags));
+        let result = self.dispatch("setxattr", ino, |fs, node| {
+            node.try_borrow_resource()?.setxattr(&mut fs.request(ino, req), name, value, flags)
+        });
+        // Setting an ACL can change the mode
+        self.attr_cache.invalidate(ino);
+        trace!("setxattr(...) = {:?}", result);
+        attempt!(reply, result);
+
+        reply.ok();
+    }
+    fn listxattr(&mut self, req: &FuseRequest<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        let ino = Inode(ino);
+        let result = self.dispatch("listxattr", ino, |fs, node| {
+            let mut names = Vec::new();
+            node.try_borrow_resource()?.listxattr(&mut fs.request(ino, req), &mut names)?;
+            Ok(names)
+        });
+        trace!("listxattr(...) = {:?}", result);
+        let names = attempt!(reply, result);
+
+        // The kernel expects each name to be null terminated
+        let mut value = Vec::new();
+        for name in names {
+            value.extend_from_slice(name.as_bytes());
+            value.push(0);
         }
+        reply_xattr(reply, size, &value);
+    }
+    fn removexattr(&mut self, req: &FuseRequest<'_>, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        let ino = Inode(ino);
+        let result = self.dispatch("removexattr", ino, |fs, node| {
+            node.try_borrow_resource()?.removexattr(&mut fs.request(ino, req), name)
+        });
+        self.attr_cache.invalidate(ino);
+        trace!("removexattr(...) = {:?}", result);
+        attempt!(reply, result);
+
+        reply.ok();
     }
 
     //  _____ ___  ____   ___
@@ -262,84 +1114,241 @@ impl Filesystem for EasyFuse {
 
     /*
     // ENOSYS
-    fn setattr(&mut self, _req: &FuseRequest, _ino: u64, _mode: Option<u32>, _uid: Option<u32>, _gid: Option<u32>, _size: Option<u64>, _atime: Option<Timespec>, _mtime: Option<Timespec>, _fh: Option<u64>, _crtime: Option<Timespec>, _chgtime: Option<Timespec>, _bkuptime: Option<Timespec>, _flags: Option<u32>, reply: ReplyAttr) {
+    fn setattr(&mut self, _req: &FuseRequest<'_>, _ino: u64, _mode: Option<u32>, _uid: Option<u32>, _gid: Option<u32>, _size: Option<u64>, _atime: Option<TimeOrNow>, _mtime: Option<TimeOrNow>, _ctime: Option<SystemTime>, _fh: Option<u64>, _crtime: Option<SystemTime>, _chgtime: Option<SystemTime>, _bkuptime: Option<SystemTime>, _flags: Option<u32>, reply: ReplyAttr) {
         reply.error(libc::ENOSYS);
     }
-    fn readlink(&mut self, _req: &FuseRequest, _ino: u64, reply: ReplyData) {
+    fn readlink(&mut self, _req: &FuseRequest<'_>, _ino: u64, reply: ReplyData) {
         reply.error(libc::ENOSYS);
     }
-    fn mknod(&mut self, _req: &FuseRequest, _parent: u64, _name: &OsStr, _mode: u32, _rdev: u32, reply: ReplyEntry) {
+    fn mknod(&mut self, _req: &FuseRequest<'_>, _parent: u64, _name: &OsStr, _mode: u32, _umask: u32, _rdev: u32, reply: ReplyEntry) {
         reply.error(libc::ENOSYS);
     }
-    fn mkdir(&mut self, _req: &FuseRequest, _parent: u64, _name: &OsStr, _mode: u32, reply: ReplyEntry) {
+    fn mkdir(&mut self, _req: &FuseRequest<'_>, _parent: u64, _name: &OsStr, _mode: u32, _umask: u32, reply: ReplyEntry) {
         reply.error(libc::ENOSYS);
     }
-    fn unlink(&mut self, _req: &FuseRequest, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+    fn rmdir(&mut self, _req: &FuseRequest<'_>, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
         reply.error(libc::ENOSYS);
     }
-    fn rmdir(&mut self, _req: &FuseRequest, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+    fn rename(&mut self, _req: &FuseRequest<'_>, _parent: u64, _name: &OsStr, _newparent: u64, _newname: &OsStr, _flags: u32, reply: ReplyEmpty) {
         reply.error(libc::ENOSYS);
     }
-    fn rename(&mut self, _req: &FuseRequest, _parent: u64, _name: &OsStr, _newparent: u64, _newname: &OsStr, reply: ReplyEmpty) {
+    fn write(&mut self, _req: &FuseRequest<'_>, _ino: u64, _fh: u64, _offset: i64, _data: &[u8], _write_flags: u32, _flags: i32, _lock_owner: Option<u64>, reply: ReplyWrite) {
         reply.error(libc::ENOSYS);
     }
-    fn link(&mut self, _req: &FuseRequest, _ino: u64, _newparent: u64, _newname: &OsStr, reply: ReplyEntry) {
+    fn flush(&mut self, _req: &FuseRequest<'_>, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
         reply.error(libc::ENOSYS);
     }
-    fn write(&mut self, _req: &FuseRequest, _ino: u64, _fh: u64, _offset: i64, _data: &[u8], _flags: u32, reply: ReplyWrite) {
+    fn fsync(&mut self, _req: &FuseRequest<'_>, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
         reply.error(libc::ENOSYS);
     }
-    fn flush(&mut self, _req: &FuseRequest, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+    fn fsyncdir(&mut self, _req: &FuseRequest<'_>, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
         reply.error(libc::ENOSYS);
     }
-    fn fsync(&mut self, _req: &FuseRequest, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+    fn access(&mut self, _req: &FuseRequest<'_>, _ino: u64, _mask: i32, reply: ReplyEmpty) {
         reply.error(libc::ENOSYS);
     }
-    fn fsyncdir(&mut self, _req: &FuseRequest, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+    fn create(&mut self, _req: &FuseRequest<'_>, _parent: u64, _name: &OsStr, _mode: u32, _umask: u32, _flags: i32, reply: ReplyCreate) {
         reply.error(libc::ENOSYS);
     }
-    fn setxattr(&mut self, _req: &FuseRequest, _ino: u64, _name: &OsStr, _value: &[u8], _flags: u32, _position: u32, reply: ReplyEmpty) {
+    fn getlk(&mut self, _req: &FuseRequest<'_>, _ino: u64, _fh: u64, _lock_owner: u64, _start: u64, _end: u64, _typ: i32, _pid: u32, reply: ReplyLock) {
         reply.error(libc::ENOSYS);
     }
-    fn getxattr(&mut self, _req: &FuseRequest, _ino: u64, _name: &OsStr, _size: u32, reply: ReplyXattr) {
+    fn setlk(&mut self, _req: &FuseRequest<'_>, _ino: u64, _fh: u64, _lock_owner: u64, _start: u64, _end: u64, _typ: i32, _pid: u32, _sleep: bool, reply: ReplyEmpty) {
         reply.error(libc::ENOSYS);
     }
-    fn listxattr(&mut self, _req: &FuseRequest, _ino: u64, _size: u32, reply: ReplyXattr) {
+    fn bmap(&mut self, _req: &FuseRequest<'_>, _ino: u64, _blocksize: u32, _idx: u64, reply: ReplyBmap) {
         reply.error(libc::ENOSYS);
     }
-    fn removexattr(&mut self, _req: &FuseRequest, _ino: u64, _name: &OsStr, reply: ReplyEmpty) {
+    fn fallocate(&mut self, _req: &FuseRequest<'_>, _ino: u64, _fh: u64, _offset: i64, _length: i64, _mode: i32, reply: ReplyEmpty) {
         reply.error(libc::ENOSYS);
     }
-    fn access(&mut self, _req: &FuseRequest, _ino: u64, _mask: u32, reply: ReplyEmpty) {
+    fn lseek(&mut self, _req: &FuseRequest<'_>, _ino: u64, _fh: u64, _offset: i64, _whence: i32, reply: ReplyLseek) {
         reply.error(libc::ENOSYS);
     }
-    fn create(&mut self, _req: &FuseRequest, _parent: u64, _name: &OsStr, _mode: u32, _flags: u32, reply: ReplyCreate) {
+    fn copy_file_range(&mut self, _req: &FuseRequest<'_>, _ino_in: u64, _fh_in: u64, _offset_in: i64, _ino_out: u64, _fh_out: u64, _offset_out: i64, _len: u64, _flags: u32, reply: ReplyWrite) {
         reply.error(libc::ENOSYS);
     }
-    fn getlk(&mut self, _req: &FuseRequest, _ino: u64, _fh: u64, _lock_owner: u64, _start: u64, _end: u64, _typ: u32, _pid: u32, reply: ReplyLock) {
-        reply.error(libc::ENOSYS);
+
+    // Has default impls
+    fn destroy(&mut self) {}
+    fn statfs(&mut self, _req: &FuseRequest<'_>, _ino: u64, reply: ReplyStatfs) {
+        reply.statfs(0, 0, 0, 0, 0, 512, 255, 0);
     }
-    fn setlk(&mut self, _req: &FuseRequest, _ino: u64, _fh: u64, _lock_owner: u64, _start: u64, _end: u64, _typ: u32, _pid: u32, _sleep: bool, reply: ReplyEmpty) {
-        reply.error(libc::ENOSYS);
+     */
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifier_invalidates_own_cache() {
+        let mut fs = EasyFuse::default();
+        let (parent, child) = (Inode(2), Inode(3));
+        let mut entry = returns::Entry::from(AttrBuilder::file().build());
+        entry.attr.inner.ino = child.0;
+        let ttl = Duration::from_secs(30);
+        fs.attr_cache.insert_entry(parent, OsStr::new("file"), entry, ttl);
+        fs.attr_cache.insert_attr(parent, AttrBuilder::directory().build(), ttl);
+
+        // Not mounted, so the kernel can't be told, but the internal
+        // cache should still be dropped
+        let notifier = fs.notifier();
+        assert!(notifier.inval_inode(child, -1, 0).is_err());
+        fs.apply_invalidations();
+        assert!(fs.attr_cache.attr(child).is_none());
+        assert!(fs.attr_cache.entry(parent, OsStr::new("file")).is_none());
+        assert!(fs.attr_cache.attr(parent).is_some());
+
+        fs.attr_cache.insert_entry(parent, OsStr::new("file"), entry, ttl);
+        assert!(notifier.inval_entry(parent, OsStr::new("file")).is_err());
+        fs.apply_invalidations();
+        assert!(fs.attr_cache.entry(parent, OsStr::new("file")).is_none());
+        assert!(fs.attr_cache.attr(child).is_some());
     }
-    fn bmap(&mut self, _req: &FuseRequest, _ino: u64, _blocksize: u32, _idx: u64, reply: ReplyBmap) {
-        reply.error(libc::ENOSYS);
+
+    fn with_root(root: dir::StaticDirectory) -> EasyFuse {
+        let mut fs = EasyFuse::new();
+        fs.set_root(root);
+        fs
+    }
+    fn file() -> file::StaticFile {
+        file::StaticFile::new(AttrBuilder::file().build())
     }
 
-    // Has default impls
-    fn init(&mut self, _req: &FuseRequest) -> Result<(), libc::c_int> {
-        Ok(())
+    #[test]
+    fn unregistered_with_last_name() {
+        let mut fs = with_root(dir::StaticDirectory::new(AttrBuilder::directory().build()));
+        let inode = fs.insert_path("a", file()).unwrap();
+        fs.link_path(inode, "b").unwrap();
+        assert_eq!(fs.links(inode), 2);
+
+        fs.remove_path("a").unwrap();
+        assert!(fs.resolve_unlayered(inode).is_some());
+        fs.remove_path("b").unwrap();
+        assert!(fs.resolve_unlayered(inode).is_none());
     }
-    fn destroy(&mut self, _req: &FuseRequest) {}
-    fn forget(&mut self, _req: &FuseRequest, _ino: u64, _nlookup: u64) {}
-    fn opendir(&mut self, _req: &FuseRequest, _ino: u64, _flags: u32, reply: ReplyOpen) {
-        reply.opened(0, 0);
+    #[test]
+    fn bound_elsewhere_never_unregistered() {
+        let mut fs = EasyFuse::new();
+        let inode = fs.register(file());
+        let mut root = dir::StaticDirectory::new(AttrBuilder::directory().build());
+        root.bind("a", inode);
+        fs.set_root(root);
+
+        fs.remove_path("a").unwrap();
+        assert!(fs.resolve_unlayered(inode).is_some());
     }
-    fn releasedir(&mut self, _req: &FuseRequest, _ino: u64, _fh: u64, _flags: u32, reply: ReplyEmpty) {
-        reply.ok();
+    #[test]
+    fn unregistered_once_kernel_lets_go() {
+        let mut fs = with_root(dir::StaticDirectory::new(AttrBuilder::directory().build()));
+        let inode = fs.insert_path("a", file()).unwrap();
+        fs.refs.lookup(inode);
+        fs.refs.open(inode);
+        fs.remove_path("a").unwrap();
+
+        // Like `forget` and `release`
+        fs.refs.forget(inode, 1);
+        fs.reap(inode);
+        assert!(fs.resolve_unlayered(inode).is_some());
+        fs.refs.release(inode);
+        fs.reap(inode);
+        assert!(fs.resolve_unlayered(inode).is_none());
     }
-    fn statfs(&mut self, _req: &FuseRequest, _ino: u64, reply: ReplyStatfs) {
-        reply.statfs(0, 0, 0, 0, 0, 512, 255, 0);
+
+    fn open_root(fs: &mut EasyFuse, snapshot: Option<Rc<[returns::DirEntry]>>) -> u64 {
+        let fh = fs.dir_handles.len() as u64;
+        fs.dir_handles.insert(fh, DirHandle { inner: FileHandle(0), snapshot });
+        fh
+    }
+    fn list(fs: &mut EasyFuse, fh: u64, offset: i64) -> Vec<(String, i64)> {
+        fs.list_dir_with("readdir", ROOT_ID, fh, offset, |fs, node, inner, cursor, output| {
+            node.try_borrow_resource()?.readdir_from(&mut fs.internal_request(ROOT_ID), inner, cursor, output)
+        })
+            .unwrap()
+            .into_iter()
+            .map(|(entry, offset)| (entry.name.to_string_lossy().into_owned(), offset))
+            .collect()
+    }
+    fn names(page: &[(String, i64)]) -> Vec<&str> {
+        page.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn dot_entries_come_first() {
+        let mut fs = with_root(dir::StaticDirectory::new(AttrBuilder::directory().build()));
+        for name in &["a", "b", "c"] {
+            fs.insert_path(name, file()).unwrap();
+        }
+        let fh = open_root(&mut fs, None);
+
+        let all = list(&mut fs, fh, 0);
+        assert_eq!(names(&all), [".", "..", "a", "b", "c"]);
+        assert_eq!((all[0].1, all[1].1), (1, DOT_ENTRIES));
+        assert!(all.windows(2).all(|pair| pair[0].1 < pair[1].1));
+
+        // Each offset continues right after its entry
+        for (i, (_, offset)) in all.iter().enumerate() {
+            assert_eq!(list(&mut fs, fh, *offset), all[i + 1..]);
+        }
+    }
+    #[test]
+    fn paging_across_changes() {
+        let mut fs = with_root(dir::StaticDirectory::new(AttrBuilder::directory().build()));
+        for name in &["a", "b", "c"] {
+            fs.insert_path(name, file()).unwrap();
+        }
+        let fh = open_root(&mut fs, None);
+        let first = list(&mut fs, fh, 0);
+        let (_, after_a) = first[2];
+
+        // Changing what was already listed doesn't shift the rest
+        fs.remove_path("a").unwrap();
+        fs.insert_path("d", file()).unwrap();
+        assert_eq!(names(&list(&mut fs, fh, after_a)), ["b", "c", "d"]);
+        fs.remove_path("b").unwrap();
+        assert_eq!(names(&list(&mut fs, fh, after_a)), ["c", "d"]);
+    }
+    #[test]
+    fn snapshots_page_by_position() {
+        let mut fs = with_root(dir::StaticDirectory::new(AttrBuilder::directory().build()));
+        let mut snapshot = Vec::new();
+        for (offset, name) in (1..).zip(&["a", "b", "c"]) {
+            let inode = fs.insert_path(name, file()).unwrap();
+            snapshot.push(returns::DirEntry::new(inode, FileType::RegularFile, OsStr::new(name)).with_offset(offset));
+        }
+        let fh = open_root(&mut fs, Some(Rc::from(snapshot)));
+        fs.remove_path("b").unwrap();
+
+        let all = list(&mut fs, fh, 0);
+        assert_eq!(all.iter().map(|(_, offset)| *offset).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+        assert_eq!(names(&all), [".", "..", "a", "b", "c"]);
+        assert_eq!(list(&mut fs, fh, 3), all[3..]);
+        assert_eq!(list(&mut fs, fh, 5), []);
+    }
+    #[test]
+    fn listing_in_batches() {
+        let mut fs = with_root(dir::StaticDirectory::new(AttrBuilder::directory().build()));
+        let count = LIST_BATCH + 10;
+        for i in 0..count {
+            fs.insert_path(format!("{:03}", i), file()).unwrap();
+        }
+        let fh = open_root(&mut fs, None);
+
+        let mut seen = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = list(&mut fs, fh, offset);
+            assert!(page.len() <= LIST_BATCH);
+            match page.last() {
+                Some((_, last)) => offset = *last,
+                None => break,
+            }
+            seen.extend(page.into_iter().map(|(name, _)| name));
+        }
+        let mut expected = vec![".".to_string(), "..".to_string()];
+        expected.extend((0..count).map(|i| format!("{:03}", i)));
+        assert_eq!(seen, expected);
     }
-     */
 }
+
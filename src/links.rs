@@ -0,0 +1,173 @@
+//! An index of which names are bound to which inodes, as seen through
+//! lookups and directory listings, used to compute link counts
+
+use crate::Inode;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::{OsStr, OsString},
+};
+
+use fuser::FileType;
+
+/// The bindings of every directory that has been looked into
+#[derive(Debug, Default)]
+pub(crate) struct LinkIndex {
+    /// The inode and file type behind each name in each directory
+    bindings: BTreeMap<(Inode, OsString), (Inode, FileType)>,
+    /// The number of names bound to each inode
+    links: BTreeMap<Inode, u32>,
+    /// The number of subdirectories of each directory
+    subdirs: BTreeMap<Inode, u32>,
+    /// Inodes whose bindings are all known, because they were only
+    /// ever bound through `EasyFuse`
+    complete: BTreeSet<Inode>,
+    /// Complete inodes that lost their last binding
+    orphans: BTreeSet<Inode>,
+}
+impl LinkIndex {
+    /// Record that `name` in `parent` is bound to `inode`. Returns
+    /// false if this was already known.
+    pub(crate) fn bind(&mut self, parent: Inode, name: &OsStr, inode: Inode, kind: FileType) -> bool {
+        let key = (parent, name.to_os_string());
+        if self.bindings.get(&key) == Some(&(inode, kind)) {
+            return false;
+        }
+        if let Some(old) = self.bindings.insert(key, (inode, kind)) {
+            self.count(parent, old, false);
+        }
+        self.count(parent, (inode, kind), true);
+        self.orphans.remove(&inode);
+        true
+    }
+    /// Forget that `name` in `parent` is bound, returning the inode it
+    /// was bound to
+    pub(crate) fn unbind(&mut self, parent: Inode, name: &OsStr) -> Option<Inode> {
+        let old = self.bindings.remove(&(parent, name.to_os_string()))?;
+        self.count(parent, old, false);
+        Some(old.0)
+    }
+    /// Forget all bindings in and to `inode`, returning the
+    /// directories `inode` was bound in
+    pub(crate) fn forget(&mut self, inode: Inode) -> Vec<Inode> {
+        let stale: Vec<_> = self.bindings.iter()
+            .filter(|((parent, _), (child, _))| *parent == inode || *child == inode)
+            .map(|((parent, name), _)| (*parent, name.clone()))
+            .collect();
+        let mut parents = Vec::new();
+        for (parent, name) in stale {
+            if self.unbind(parent, &name) == Some(inode) {
+                parents.push(parent);
+            }
+        }
+        self.complete.remove(&inode);
+        self.orphans.remove(&inode);
+        parents
+    }
+
+    /// Record that every binding of `inode` is known, so that once it
+    /// has none left it can be considered an orphan
+    pub(crate) fn complete(&mut self, inode: Inode) {
+        self.complete.insert(inode);
+    }
+    /// Mark `inode` as an orphan if its bindings are complete and
+    /// there are none left. Returns true if it is one.
+    pub(crate) fn orphan(&mut self, inode: Inode) -> bool {
+        if self.complete.contains(&inode) && self.links(inode) == 0 {
+            self.orphans.insert(inode);
+        }
+        self.is_orphan(inode)
+    }
+    /// Returns true if `inode` is known to have no bindings left
+    pub(crate) fn is_orphan(&self, inode: Inode) -> bool {
+        self.orphans.contains(&inode)
+    }
+
+    /// The number of known names bound to `inode`
+    pub(crate) fn links(&self, inode: Inode) -> u32 {
+        self.links.get(&inode).copied().unwrap_or(0)
+    }
+    /// The link count of `inode`. A directory has one link from its
+    /// parent, one from its own `.` and one from the `..` of each
+    /// subdirectory. Anything else has one link per name, but at
+    /// least one unless it's an orphan.
+    pub(crate) fn nlink(&self, inode: Inode, kind: FileType) -> u32 {
+        if self.is_orphan(inode) {
+            0
+        } else if kind == FileType::Directory {
+            self.subdirs.get(&inode).copied().unwrap_or(0).saturating_add(2)
+        } else {
+            self.links(inode).max(1)
+        }
+    }
+
+    fn count(&mut self, parent: Inode, (inode, kind): (Inode, FileType), add: bool) {
+        adjust(&mut self.links, inode, add);
+        if kind == FileType::Directory {
+            adjust(&mut self.subdirs, parent, add);
+        }
+    }
+}
+
+/// Add or remove one from a counter, dropping it at zero
+fn adjust(counters: &mut BTreeMap<Inode, u32>, key: Inode, add: bool) {
+    let count = counters.entry(key).or_insert(0);
+    *count = if add { count.saturating_add(1) } else { count.saturating_sub(1) };
+    if *count == 0 {
+        counters.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIR: Inode = Inode(1);
+
+    #[test]
+    fn directories_count_subdirectories() {
+        let mut index = LinkIndex::default();
+        assert_eq!(index.nlink(DIR, FileType::Directory), 2);
+        index.bind(DIR, OsStr::new("a"), Inode(2), FileType::Directory);
+        index.bind(DIR, OsStr::new("b"), Inode(3), FileType::Directory);
+        index.bind(DIR, OsStr::new("c"), Inode(4), FileType::RegularFile);
+        assert_eq!(index.nlink(DIR, FileType::Directory), 4);
+        assert_eq!(index.nlink(Inode(2), FileType::Directory), 2);
+
+        index.unbind(DIR, OsStr::new("a"));
+        assert_eq!(index.nlink(DIR, FileType::Directory), 3);
+        // Rebinding a name to a file drops the subdirectory it replaces
+        index.bind(DIR, OsStr::new("b"), Inode(4), FileType::RegularFile);
+        assert_eq!(index.nlink(DIR, FileType::Directory), 2);
+        assert_eq!(index.nlink(Inode(4), FileType::RegularFile), 2);
+    }
+    #[test]
+    fn orphaned_once_last_name_is_gone() {
+        let mut index = LinkIndex::default();
+        let file = Inode(2);
+        index.bind(DIR, OsStr::new("a"), file, FileType::RegularFile);
+        index.bind(DIR, OsStr::new("b"), file, FileType::RegularFile);
+        index.complete(file);
+
+        index.unbind(DIR, OsStr::new("a"));
+        assert!(!index.orphan(file));
+        assert_eq!(index.nlink(file, FileType::RegularFile), 1);
+        index.unbind(DIR, OsStr::new("b"));
+        assert!(index.orphan(file));
+        assert_eq!(index.nlink(file, FileType::RegularFile), 0);
+
+        // Binding it again brings it back
+        index.bind(DIR, OsStr::new("c"), file, FileType::RegularFile);
+        assert!(!index.is_orphan(file));
+    }
+    #[test]
+    fn incomplete_never_orphaned() {
+        let mut index = LinkIndex::default();
+        let file = Inode(2);
+        index.bind(DIR, OsStr::new("a"), file, FileType::RegularFile);
+        index.unbind(DIR, OsStr::new("a"));
+        assert!(!index.orphan(file));
+        // It may still be bound somewhere that hasn't been seen
+        assert_eq!(index.nlink(file, FileType::RegularFile), 1);
+    }
+}
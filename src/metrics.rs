@@ -0,0 +1,206 @@
+//! Counters and latency histograms for every dispatched operation, to
+//! find out which inodes are hot and where errors come from
+
+use crate::{Inode, Result};
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt::Write,
+    time::Duration,
+};
+
+use libc::c_int;
+
+/// Upper bounds of the latency histogram buckets, in microseconds
+const BUCKETS_US: [u64; 7] = [10, 100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+/// How many of the hottest inodes are included when rendering
+const RENDERED_INODES: usize = 20;
+
+/// A histogram of latencies, with fixed buckets from 10µs to 10s
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS_US.len()],
+    count: u64,
+    sum: Duration,
+}
+impl Histogram {
+    fn observe(&mut self, latency: Duration) {
+        let us = u64::try_from(latency.as_micros()).unwrap_or(u64::max_value());
+        if let Some(i) = BUCKETS_US.iter().position(|&bound| us <= bound) {
+            self.buckets[i] = self.buckets[i].saturating_add(1);
+        }
+        self.count = self.count.saturating_add(1);
+        self.sum = self.sum.checked_add(latency).unwrap_or(self.sum);
+    }
+
+    /// The number of observations
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    /// The sum of all observed latencies
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+    /// Each bucket's upper bound along with the number of
+    /// observations at or below it. Observations above the last bound
+    /// are only part of `count`.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        BUCKETS_US.iter()
+            .zip(self.buckets.iter())
+            .scan(0_u64, |total, (&bound, &count)| {
+                *total = total.saturating_add(count);
+                Some((Duration::from_micros(bound), *total))
+            })
+    }
+}
+
+/// Statistics of a single kind of operation
+#[derive(Debug, Clone, Default)]
+pub struct OpStats {
+    /// How many times it was called
+    pub calls: u64,
+    /// How many more times it was answered from `EasyFuse`'s own
+    /// cache instead, without calling the resource. These aren't part
+    /// of `calls` or any of the other statistics.
+    pub cached: u64,
+    /// How many times it failed, by errno
+    pub errors: BTreeMap<c_int, u64>,
+    /// How many bytes were transferred, for operations like `read`
+    pub bytes: u64,
+    /// How long it took
+    pub latency: Histogram,
+}
+
+/// Statistics of all operations dispatched by `EasyFuse`, see
+/// `EasyFuse::metrics`
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    operations: BTreeMap<&'static str, OpStats>,
+    inodes: BTreeMap<Inode, u64>,
+}
+impl Metrics {
+    pub(crate) fn record<T>(&mut self, operation: &'static str, inode: Inode, result: &Result<T>, latency: Duration) {
+        let stats = self.operations.entry(operation).or_default();
+        stats.calls = stats.calls.saturating_add(1);
+        if let Err(err) = result {
+            let errors = stats.errors.entry(err.errno()).or_insert(0);
+            *errors = errors.saturating_add(1);
+        }
+        stats.latency.observe(latency);
+
+        let calls = self.inodes.entry(inode).or_insert(0);
+        *calls = calls.saturating_add(1);
+    }
+    pub(crate) fn record_cached(&mut self, operation: &'static str) {
+        let stats = self.operations.entry(operation).or_default();
+        stats.cached = stats.cached.saturating_add(1);
+    }
+    pub(crate) fn add_bytes(&mut self, operation: &'static str, bytes: usize) {
+        let stats = self.operations.entry(operation).or_default();
+        stats.bytes = stats.bytes.saturating_add(u64::try_from(bytes).unwrap_or(u64::max_value()));
+    }
+    pub(crate) fn forget(&mut self, inode: Inode) {
+        self.inodes.remove(&inode);
+    }
+
+    /// The statistics of an operation, like `"read"`, if it has been
+    /// called
+    pub fn operation(&self, operation: &str) -> Option<&OpStats> {
+        self.operations.get(operation)
+    }
+    /// The statistics of all operations that have been called
+    pub fn operations(&self) -> impl Iterator<Item = (&'static str, &OpStats)> + '_ {
+        self.operations.iter().map(|(&operation, stats)| (operation, stats))
+    }
+    /// The number of operations dispatched to `inode`
+    pub fn inode_calls(&self, inode: Inode) -> u64 {
+        self.inodes.get(&inode).copied().unwrap_or(0)
+    }
+    /// The `n` inodes with the most dispatched operations, hottest
+    /// first
+    pub fn hottest(&self, n: usize) -> Vec<(Inode, u64)> {
+        let mut inodes: Vec<_> = self.inodes.iter().map(|(&inode, &calls)| (inode, calls)).collect();
+        inodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        inodes.truncate(n);
+        inodes
+    }
+    /// Reset all statistics
+    pub fn reset(&mut self) {
+        self.operations.clear();
+        self.inodes.clear();
+    }
+
+    /// Render all statistics in the Prometheus text format. Only the
+    /// hottest inodes are included, to keep the number of series down.
+    pub fn render_prometheus(&self) -> String {
+        // Writing to a String never fails
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP easyfuse_operations_total Number of dispatched operations.");
+        let _ = writeln!(out, "# TYPE easyfuse_operations_total counter");
+        for (operation, stats) in self.operations() {
+            let _ = writeln!(out, "easyfuse_operations_total{{op=\"{}\"}} {}", operation, stats.calls);
+        }
+
+        let _ = writeln!(out, "# HELP easyfuse_cache_hits_total Number of operations answered from the cache instead.");
+        let _ = writeln!(out, "# TYPE easyfuse_cache_hits_total counter");
+        for (operation, stats) in self.operations().filter(|(_, stats)| stats.cached > 0) {
+            let _ = writeln!(out, "easyfuse_cache_hits_total{{op=\"{}\"}} {}", operation, stats.cached);
+        }
+
+        let _ = writeln!(out, "# HELP easyfuse_errors_total Number of failed operations, by errno.");
+        let _ = writeln!(out, "# TYPE easyfuse_errors_total counter");
+        for (operation, stats) in self.operations() {
+            for (errno, count) in &stats.errors {
+                let _ = writeln!(out, "easyfuse_errors_total{{op=\"{}\",errno=\"{}\"}} {}", operation, errno, count);
+            }
+        }
+
+        let _ = writeln!(out, "# HELP easyfuse_bytes_total Number of bytes transferred.");
+        let _ = writeln!(out, "# TYPE easyfuse_bytes_total counter");
+        for (operation, stats) in self.operations().filter(|(_, stats)| stats.bytes > 0) {
+            let _ = writeln!(out, "easyfuse_bytes_total{{op=\"{}\"}} {}", operation, stats.bytes);
+        }
+
+        let _ = writeln!(out, "# HELP easyfuse_latency_seconds Time spent in each operation.");
+        let _ = writeln!(out, "# TYPE easyfuse_latency_seconds histogram");
+        for (operation, stats) in self.operations() {
+            for (bound, count) in stats.latency.buckets() {
+                let _ = writeln!(out, "easyfuse_latency_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}", operation, bound.as_secs_f64(), count);
+            }
+            let _ = writeln!(out, "easyfuse_latency_seconds_bucket{{op=\"{}\",le=\"+Inf\"}} {}", operation, stats.latency.count());
+            let _ = writeln!(out, "easyfuse_latency_seconds_sum{{op=\"{}\"}} {}", operation, stats.latency.sum().as_secs_f64());
+            let _ = writeln!(out, "easyfuse_latency_seconds_count{{op=\"{}\"}} {}", operation, stats.latency.count());
+        }
+
+        let _ = writeln!(out, "# HELP easyfuse_inode_operations_total Number of operations on the hottest inodes.");
+        let _ = writeln!(out, "# TYPE easyfuse_inode_operations_total counter");
+        for (inode, calls) in self.hottest(RENDERED_INODES) {
+            let _ = writeln!(out, "easyfuse_inode_operations_total{{inode=\"{}\"}} {}", inode.0, calls);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_hits_counted_apart() {
+        let mut metrics = Metrics::default();
+        metrics.record("getattr", Inode(2), &Ok(()), Duration::from_micros(5));
+        metrics.record_cached("getattr");
+        metrics.record_cached("getattr");
+
+        let stats = metrics.operation("getattr").unwrap();
+        assert_eq!((stats.calls, stats.cached, stats.latency.count()), (1, 2, 1));
+        assert_eq!(metrics.inode_calls(Inode(2)), 1);
+
+        let rendered = metrics.render_prometheus();
+        assert!(rendered.contains("easyfuse_operations_total{op=\"getattr\"} 1\n"));
+        assert!(rendered.contains("easyfuse_cache_hits_total{op=\"getattr\"} 2\n"));
+    }
+}
@@ -0,0 +1,86 @@
+//! A handle for telling the kernel to drop its cached data, so that
+//! resources which change behind its back can still use long TTLs
+
+use crate::{Error, Inode, Result};
+
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Something to drop from `EasyFuse`'s own cache the next time it
+/// handles an operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Invalidation {
+    Inode(Inode),
+    Entry(Inode, OsString),
+}
+
+/// A cloneable handle to invalidate kernel caches. It's only
+/// connected while the filesystem is mounted through
+/// `EasyFuse::mount`, and can be sent to other threads. Don't call
+/// it from inside a resource for an operation on the same inode, as
+/// the kernel may be waiting on that operation to finish.
+///
+/// Invalidations also apply to the attributes and entries `EasyFuse`
+/// caches itself, see `CachePolicy`. Those are queued up and dropped
+/// before the next operation, even while not mounted.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    inner: Arc<Mutex<Option<fuser::Notifier>>>,
+    pending: Arc<Mutex<Vec<Invalidation>>>,
+}
+impl Notifier {
+    pub(crate) fn connect(&self, notifier: fuser::Notifier) {
+        *lock(&self.inner) = Some(notifier);
+    }
+    pub(crate) fn disconnect(&self) {
+        *lock(&self.inner) = None;
+    }
+    /// Take the invalidations queued up since the last call
+    pub(crate) fn take_pending(&self) -> Vec<Invalidation> {
+        std::mem::take(&mut *lock(&self.pending))
+    }
+    fn with<F>(&self, invalidation: Invalidation, f: F) -> Result<()>
+    where
+        F: FnOnce(&fuser::Notifier) -> std::io::Result<()>
+    {
+        lock(&self.pending).push(invalidation);
+        let guard = lock(&self.inner);
+        let notifier = guard.as_ref().ok_or(Error::Errno(libc::ENOTCONN))?;
+        f(notifier).map_err(Error::from)
+    }
+
+    /// Returns true if the filesystem is currently mounted, so that
+    /// notifications can be sent
+    pub fn is_connected(&self) -> bool {
+        lock(&self.inner).is_some()
+    }
+
+    /// Invalidate the cached attributes of `inode`, and its cached
+    /// data from `offset` and `len` bytes on. A `len` of zero or less
+    /// means until the end of the file, and a negative `offset` only
+    /// invalidates the attributes.
+    pub fn inval_inode(&self, inode: Inode, offset: i64, len: i64) -> Result<()> {
+        self.with(Invalidation::Inode(inode), |notifier| notifier.inval_inode(inode.0, offset, len))
+    }
+    /// Invalidate the cached lookup of `name` inside `parent`, so the
+    /// next access will look it up again.
+    pub fn inval_entry(&self, parent: Inode, name: &OsStr) -> Result<()> {
+        self.with(Invalidation::Entry(parent, name.to_os_string()), |notifier| notifier.inval_entry(parent.0, name))
+    }
+}
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notifier")
+            .field("connected", &self.is_connected())
+            .finish()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // The lock is never held across anything that can panic, but
+    // there's no harm in recovering just in case
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
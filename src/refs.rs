@@ -0,0 +1,83 @@
+//! What the kernel still holds on to, so that unlinked inodes are only
+//! unregistered once nothing refers to them anymore
+
+use crate::Inode;
+
+use std::collections::BTreeMap;
+
+/// The lookup count and number of open handles of every inode the
+/// kernel knows about
+#[derive(Debug, Default)]
+pub(crate) struct KernelRefs {
+    /// The number of entries sent for each inode and not forgotten yet
+    lookups: BTreeMap<Inode, u64>,
+    /// The number of open file and directory handles of each inode
+    handles: BTreeMap<Inode, u64>,
+}
+impl KernelRefs {
+    /// Record that an entry for `inode` was sent to the kernel
+    pub(crate) fn lookup(&mut self, inode: Inode) {
+        adjust(&mut self.lookups, inode, 1, true);
+    }
+    /// Record that the kernel forgot `nlookup` entries for `inode`
+    pub(crate) fn forget(&mut self, inode: Inode, nlookup: u64) {
+        adjust(&mut self.lookups, inode, nlookup, false);
+    }
+    /// Record that `inode` was opened
+    pub(crate) fn open(&mut self, inode: Inode) {
+        adjust(&mut self.handles, inode, 1, true);
+    }
+    /// Record that a handle of `inode` was released
+    pub(crate) fn release(&mut self, inode: Inode) {
+        adjust(&mut self.handles, inode, 1, false);
+    }
+    /// Forget everything about `inode`
+    pub(crate) fn clear(&mut self, inode: Inode) {
+        self.lookups.remove(&inode);
+        self.handles.remove(&inode);
+    }
+
+    /// Returns true if the kernel may still use `inode`
+    pub(crate) fn is_referenced(&self, inode: Inode) -> bool {
+        self.lookups.contains_key(&inode) || self.handles.contains_key(&inode)
+    }
+}
+
+/// Add or remove `n` from a counter, dropping it at zero
+fn adjust(counters: &mut BTreeMap<Inode, u64>, key: Inode, n: u64, add: bool) {
+    let count = counters.entry(key).or_insert(0);
+    *count = if add { count.saturating_add(n) } else { count.saturating_sub(n) };
+    if *count == 0 {
+        counters.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn referenced_until_both_reach_zero() {
+        let mut refs = KernelRefs::default();
+        let inode = Inode(2);
+        assert!(!refs.is_referenced(inode));
+
+        refs.lookup(inode);
+        refs.lookup(inode);
+        refs.open(inode);
+        refs.forget(inode, 2);
+        assert!(refs.is_referenced(inode));
+        refs.release(inode);
+        assert!(!refs.is_referenced(inode));
+
+        refs.open(inode);
+        refs.lookup(inode);
+        refs.release(inode);
+        assert!(refs.is_referenced(inode));
+        // Forgetting more than was looked up doesn't wrap around
+        refs.forget(inode, 5);
+        assert!(!refs.is_referenced(inode));
+        refs.lookup(inode);
+        assert!(refs.is_referenced(inode));
+    }
+}
@@ -1,13 +1,91 @@
 //! Convenient builder for `FileAttr`s
 
-use fuse::{FileAttr, FileType};
-use time::Timespec;
+use crate::returns;
+
+use std::{
+    convert::TryFrom,
+    fs::Metadata,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fuser::{FileAttr, FileType};
+
+/// Convert seconds and nanoseconds since the unix epoch, which may be
+/// negative, to a `SystemTime`. Times the platform can't represent
+/// become the epoch itself.
+fn unix_time(sec: i64, nsec: i64) -> SystemTime {
+    let nsec = Duration::from_nanos(u64::try_from(nsec).unwrap_or(0));
+    let time = match u64::try_from(sec) {
+        Ok(sec) => UNIX_EPOCH.checked_add(Duration::from_secs(sec)),
+        Err(_) => UNIX_EPOCH.checked_sub(Duration::from_secs(sec.unsigned_abs())),
+    };
+    time.and_then(|time| time.checked_add(nsec)).unwrap_or(UNIX_EPOCH)
+}
+
+/// The unit of `FileAttr::blocks`, which is always 512 bytes no
+/// matter the `blksize`
+pub const BLOCK_SIZE: u64 = 512;
+
+/// The number of blocks needed to store `size` bytes, rounded up, see
+/// `BLOCK_SIZE`
+#[allow(clippy::integer_arithmetic, clippy::integer_division)] // BLOCK_SIZE is not zero
+pub fn blocks_for(size: u64) -> u64 {
+    size / BLOCK_SIZE + u64::from(size % BLOCK_SIZE != 0)
+}
+
+/// Parse a permission mode, either in octal like `"0755"` or
+/// symbolic like `"rwxr-xr-x"`. The symbolic form may include the
+/// setuid, setgid and sticky bits as `s`, `S`, `t` and `T`, and may
+/// start with a file type character, as printed by `ls -l`, which is
+/// ignored.
+pub fn parse_mode(mode: &str) -> Option<u16> {
+    // The read, write, execute and special bit of each class, along
+    // with the character that shows the special bit
+    const CLASSES: [(u16, u16, u16, u16, u8); 3] = [
+        (0o400, 0o200, 0o100, 0o4000, b's'),
+        (0o040, 0o020, 0o010, 0o2000, b's'),
+        (0o004, 0o002, 0o001, 0o1000, b't'),
+    ];
+
+    if !mode.is_empty() && mode.bytes().all(|c| c.is_ascii_digit()) {
+        return u16::from_str_radix(mode, 8).ok().filter(|&mode| mode <= 0o7777);
+    }
+
+    let symbols = match mode.as_bytes() {
+        symbols if symbols.len() == 9 => symbols,
+        [kind, symbols @ ..] if symbols.len() == 9 && b"-dlcbps".contains(kind) => symbols,
+        _ => return None,
+    };
+    let mut mode = 0;
+    for (symbols, &(read, write, exec, special, special_symbol)) in symbols.chunks_exact(3).zip(CLASSES.iter()) {
+        mode |= match symbols[0] {
+            b'r' => read,
+            b'-' => 0,
+            _ => return None,
+        };
+        mode |= match symbols[1] {
+            b'w' => write,
+            b'-' => 0,
+            _ => return None,
+        };
+        mode |= match symbols[2] {
+            b'x' => exec,
+            b'-' => 0,
+            c if c == special_symbol => exec | special,
+            c if c == special_symbol.to_ascii_uppercase() => special,
+            _ => return None,
+        };
+    }
+    Some(mode)
+}
 
 macro_rules! attr_builder {
     ($($property:ident $setter:ident: $type:ty = |$self:ident| $default:expr,)*) => {
         /// A builder of `FileAttr` with sane default values
         #[derive(Debug, Default, Clone, Copy)]
         pub struct AttrBuilder {
+            ttl: Option<Duration>,
             $($property: Option<$type>,)*
         }
         impl AttrBuilder {
@@ -25,14 +103,27 @@ macro_rules! attr_builder {
                     .with_kind(FileType::Directory)
             }
 
-            /// Build a `FileAttr`, resolving all default values
-            pub fn build(self) -> FileAttr {
-                FileAttr {
+            /// Build the attributes, resolving all default values.
+            /// The TTL is zero unless set with `with_ttl`.
+            pub fn build(self) -> returns::Attr {
+                let mut attr = returns::Attr::from(FileAttr {
                     $($property: self.$property.unwrap_or_else(|| {
                         let $self = &self;
                         $default
                     }),)*
-                }
+                });
+                attr.ttl = self.ttl.unwrap_or_default();
+                attr
+            }
+
+            /// A chaining function to set how long the kernel may
+            /// cache the built attributes, see `returns::Attr::ttl`
+            pub fn with_ttl<T>(mut self, ttl: T) -> Self
+            where
+                T: Into<Option<Duration>>,
+            {
+                self.ttl = ttl.into();
+                self
             }
 
             $(
@@ -51,18 +142,114 @@ macro_rules! attr_builder {
 }
 
 attr_builder! {
-    ino     with_ino:     u64       = |_attrs|  0,
-    size    with_size:    u64       = |_attrs|  0,
-    blocks  with_blocks:  u64       = |_attrs|  0,
-    atime   with_atime:   Timespec  = |attrs|   attrs.mtime.unwrap_or_else(|| time::now().to_timespec()),
-    mtime   with_mtime:   Timespec  = |attrs|   attrs.ctime.unwrap_or_else(|| time::now().to_timespec()),
-    ctime   with_ctime:   Timespec  = |_attrs|  time::now().to_timespec(),
-    crtime  with_crtime:  Timespec  = |attrs|   attrs.ctime.unwrap_or_else(|| time::now().to_timespec()),
-    kind    with_kind:    FileType  = |_attrs|  FileType::RegularFile,
-    perm    with_perm:    u16       = |attrs|   if attrs.kind == Some(FileType::Directory) { 0o555 } else { 0o444 },
-    nlink   with_nlink:   u32       = |_attrs|  0,
-    uid     with_uid:     u32       = |_attrs|  unsafe { libc::getuid() },
-    gid     with_gid:     u32       = |_attrs|  unsafe { libc::getgid() },
-    rdev    with_rdev:    u32       = |_attrs|  0,
-    flags   with_flags:   u32       = |_attrs|  0,
+    ino     with_ino:     u64        = |_attrs| 0,
+    size    with_size:    u64        = |_attrs| 0,
+    blocks  with_blocks:  u64        = |_attrs| 0,
+    atime   with_atime:   SystemTime = |attrs|  attrs.mtime.unwrap_or_else(SystemTime::now),
+    mtime   with_mtime:   SystemTime = |attrs|  attrs.ctime.unwrap_or_else(SystemTime::now),
+    ctime   with_ctime:   SystemTime = |_attrs| SystemTime::now(),
+    crtime  with_crtime:  SystemTime = |attrs|  attrs.ctime.unwrap_or_else(SystemTime::now),
+    kind    with_kind:    FileType   = |_attrs| FileType::RegularFile,
+    perm    with_perm:    u16        = |attrs|  if attrs.kind == Some(FileType::Directory) { 0o555 } else { 0o444 },
+    nlink   with_nlink:   u32        = |_attrs| 0,
+    uid     with_uid:     u32        = |_attrs| unsafe { libc::getuid() },
+    gid     with_gid:     u32        = |_attrs| unsafe { libc::getgid() },
+    rdev    with_rdev:    u32        = |_attrs| 0,
+    blksize with_blksize: u32        = |_attrs| 0,
+    flags   with_flags:   u32        = |_attrs| 0,
+}
+
+impl AttrBuilder {
+    /// A builder with all properties copied from the metadata of a
+    /// real file, except for the inode, which `EasyFuse` assigns
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_dir() {
+            FileType::Directory
+        } else if file_type.is_symlink() {
+            FileType::Symlink
+        } else if file_type.is_block_device() {
+            FileType::BlockDevice
+        } else if file_type.is_char_device() {
+            FileType::CharDevice
+        } else if file_type.is_fifo() {
+            FileType::NamedPipe
+        } else if file_type.is_socket() {
+            FileType::Socket
+        } else {
+            FileType::RegularFile
+        };
+
+        Self::default()
+            .with_size(metadata.size())
+            .with_blocks(metadata.blocks())
+            .with_atime(unix_time(metadata.atime(), metadata.atime_nsec()))
+            .with_mtime(unix_time(metadata.mtime(), metadata.mtime_nsec()))
+            .with_ctime(unix_time(metadata.ctime(), metadata.ctime_nsec()))
+            .with_crtime(metadata.created().ok())
+            .with_kind(kind)
+            .with_perm(u16::try_from(metadata.mode() & 0o7777).ok())
+            .with_nlink(u32::try_from(metadata.nlink()).ok())
+            .with_uid(metadata.uid())
+            .with_gid(metadata.gid())
+            .with_rdev(u32::try_from(metadata.rdev()).ok())
+            .with_blksize(u32::try_from(metadata.blksize()).ok())
+    }
+    /// A chaining function to set the permissions from a mode string,
+    /// see `parse_mode`. Panics if the mode is invalid, which makes
+    /// it meant for literals.
+    pub fn with_mode(self, mode: &str) -> Self {
+        self.with_perm(parse_mode(mode).expect("invalid mode"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn octal_modes() {
+        assert_eq!(parse_mode("755"), Some(0o755));
+        assert_eq!(parse_mode("0644"), Some(0o644));
+        assert_eq!(parse_mode("4755"), Some(0o4755));
+        assert_eq!(parse_mode("17777"), None);
+        assert_eq!(parse_mode("0o755"), None);
+        assert_eq!(parse_mode("789"), None);
+        assert_eq!(parse_mode(""), None);
+    }
+    #[test]
+    fn symbolic_modes() {
+        assert_eq!(parse_mode("rwxr-xr-x"), Some(0o755));
+        assert_eq!(parse_mode("rw-r-----"), Some(0o640));
+        assert_eq!(parse_mode("---------"), Some(0));
+        assert_eq!(parse_mode("rwxr-xr-"), None);
+        assert_eq!(parse_mode("rwxr-xr-xx"), None);
+        assert_eq!(parse_mode("wrxr-xr-x"), None);
+    }
+    #[test]
+    fn special_bits() {
+        assert_eq!(parse_mode("rwsr-xr-x"), Some(0o4755));
+        assert_eq!(parse_mode("rwSr--r--"), Some(0o4644));
+        assert_eq!(parse_mode("rwxr-sr-x"), Some(0o2755));
+        assert_eq!(parse_mode("rw-r-Sr--"), Some(0o2644));
+        assert_eq!(parse_mode("rwxrwxrwt"), Some(0o1777));
+        assert_eq!(parse_mode("rwxrwxrwT"), Some(0o1776));
+        // Each special bit only goes in its own class
+        assert_eq!(parse_mode("rwtr-xr-x"), None);
+        assert_eq!(parse_mode("rwxr-xr-s"), None);
+    }
+    #[test]
+    fn type_prefix() {
+        assert_eq!(parse_mode("drwxr-xr-x"), Some(0o755));
+        assert_eq!(parse_mode("-rw-r--r--"), Some(0o644));
+        assert_eq!(parse_mode("lrwxrwxrwx"), Some(0o777));
+        assert_eq!(parse_mode("xrwxr-xr-x"), None);
+    }
+    #[test]
+    fn out_of_range_times() {
+        assert_eq!(unix_time(-1, 500_000_000), UNIX_EPOCH - Duration::from_millis(500));
+        // Must not panic, whatever the platform can represent
+        let _ = unix_time(i64::MIN, 999_999_999);
+        let _ = unix_time(i64::MAX, 999_999_999);
+    }
 }
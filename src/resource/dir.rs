@@ -1,32 +1,54 @@
 //! Different `Resource` implementations for directory-like nodes
 
 use crate::{
+    acl::AclXattrs,
     returns,
     Directory,
+    Error,
+    FileHandle,
     Inode,
+    Permissions,
     Request,
+    Resource,
+    ResourceCell,
     Result,
+    XattrFlags,
 };
 
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    cell::RefMut,
+    collections::{BTreeMap, HashMap},
     ffi::{OsStr, OsString},
+    ops::Bound,
 };
 
-use fuse::FileType;
+use fuser::FileType;
+
+#[derive(Debug, Clone, Copy)]
+struct Binding {
+    inode: Inode,
+    cookie: i64,
+}
 
 /// A simple directory that you can register files on to
 #[derive(Debug)]
 pub struct StaticDirectory {
-    binds: HashMap<OsString, Inode>,
+    binds: HashMap<OsString, Binding>,
+    order: BTreeMap<i64, OsString>,
+    next_cookie: i64,
     attr: returns::Attr,
+    acls: AclXattrs,
 }
 impl StaticDirectory {
     /// Create a new instance from a file attribute
     pub fn new(attr: returns::Attr) -> Self {
         Self {
             binds: HashMap::new(),
+            order: BTreeMap::new(),
+            next_cookie: 1,
             attr,
+            acls: AclXattrs::new(),
         }
     }
 
@@ -42,19 +64,62 @@ impl StaticDirectory {
         self.attr = attr.into();
     }
 
-    /// Bind a file onto this directory
+    /// Getter for the stored ACLs
+    pub fn acls(&self) -> &AclXattrs {
+        &self.acls
+    }
+    /// Mutable getter for the stored ACLs
+    pub fn acls_mut(&mut self) -> &mut AclXattrs {
+        &mut self.acls
+    }
+
+    /// Bind a file onto this directory. Since this can't reach
+    /// `EasyFuse`, the bound resource's parent is only recorded the
+    /// first time it's looked up or listed, and `EasyFuse::parent`
+    /// returns `None` until then. Bind through `EasyFuse::insert_path`
+    /// or `EasyFuse::link_path` instead to record it right away, or
+    /// call `EasyFuse::set_parent` yourself. Binding the same inode in
+    /// several places makes hard links, see `EasyFuse::links`.
     pub fn bind<P>(&mut self, path: P, resource: Inode)
     where
         P: Into<OsString>
     {
-        self.binds.insert(path.into(), resource);
+        let path = path.into();
+        if let Some(binding) = self.binds.get_mut(&path) {
+            binding.inode = resource;
+            return;
+        }
+        let cookie = self.next_cookie;
+        self.next_cookie = cookie.checked_add(1).expect("integer overflow");
+        self.order.insert(cookie, path.clone());
+        self.binds.insert(path, Binding { inode: resource, cookie });
     }
     /// Unbind a file from this directory
     pub fn unbind<P>(&mut self, path: P) -> Option<Inode>
     where
         P: AsRef<OsStr>
     {
-        self.binds.remove(path.as_ref())
+        let binding = self.binds.remove(path.as_ref())?;
+        self.order.remove(&binding.cookie);
+        Some(binding.inode)
+    }
+
+    fn resolve(req: &mut Request, inode: Inode) -> Result<ResourceCell> {
+        req.fs.resolve(inode).ok_or_else(|| {
+            Error::custom(format!("inode {} is bound to a StaticDirectory but not registered", inode.0))
+        })
+    }
+    /// Borrow a bound resource. This only fails if it's already in
+    /// use, which means the directory contains itself somewhere.
+    fn borrow(resource: &ResourceCell) -> Result<RefMut<'_, dyn Resource + 'static>> {
+        resource.try_borrow_mut().map_err(|_| Error::Errno(libc::ELOOP))
+    }
+    fn entry(req: &mut Request, path: &OsStr, binding: Binding) -> Result<returns::DirEntry> {
+        let resource = Self::resolve(req, binding.inode)?;
+        let stat = req.call(binding.inode, "getattr", |req| Self::borrow(&resource)?.getattr(req))?;
+        Ok(returns::DirEntry::new(binding.inode, stat.inner.kind, path.to_os_string())
+            .with_offset(binding.cookie)
+            .with_attr(stat))
     }
 }
 
@@ -64,20 +129,53 @@ impl Directory for StaticDirectory {
         self.attr.inner.kind = FileType::Directory;
         Ok(self.attr)
     }
+    fn opendir(&mut self, req: &mut Request, _flags: u32) -> Result<FileHandle> {
+        req.ensure_access_acl(&self.attr.inner, self.acls.access_acl(), Permissions::EXECUTE)?;
+        Ok(FileHandle(0))
+    }
     fn lookup(&mut self, req: &mut Request, path: &OsStr) -> Result<returns::Entry> {
-        let inode = *self.binds.get(path).ok_or(libc::ENOENT)?;
-        let resource = req.fs.resolve(inode).expect("invalid inode bound to StaticDirectory");
-        let mut stat = resource.borrow_mut().getattr(req)?;
+        let inode = self.binds.get(path).ok_or(Error::NotFound)?.inode;
+        let resource = Self::resolve(req, inode)?;
+        let mut stat = req.call(inode, "getattr", |req| Self::borrow(&resource)?.getattr(req))?;
         stat.inner.ino = inode.0;
         Ok(returns::Entry::from(stat))
     }
-    fn readdir(&mut self, req: &mut Request, output: &mut Vec<returns::DirEntry>) -> Result<()> {
-        for (path, &inode) in &self.binds {
-            let resource = req.fs.resolve(inode).expect("invalid inode bound to StaticDirectory");
-            let mut stat = resource.borrow_mut().getattr(req)?;
-            stat.inner.ino = inode.0;
-            output.push(returns::DirEntry::new(inode, stat.inner.kind, path.clone()))
+    fn readdir(&mut self, req: &mut Request, _fh: FileHandle, output: &mut Vec<returns::DirEntry>) -> Result<()> {
+        for path in self.order.values() {
+            output.push(Self::entry(req, path, self.binds[path])?);
+        }
+        Ok(())
+    }
+    fn readdir_from(&mut self, req: &mut Request, _fh: FileHandle, offset: i64, output: &mut returns::DirWriter) -> Result<()> {
+        for path in self.order.range((Bound::Excluded(offset), Bound::Unbounded)).map(|(_, path)| path) {
+            if !output.push(Self::entry(req, path, self.binds[path])?) {
+                break;
+            }
+        }
+        Ok(())
+    }
+    fn link(&mut self, _req: &mut Request, path: &OsStr, inode: Inode) -> Result<()> {
+        if self.binds.contains_key(path) {
+            return Err(Error::Errno(libc::EEXIST));
         }
+        self.bind(path, inode);
+        Ok(())
+    }
+    fn unlink(&mut self, _req: &mut Request, path: &OsStr) -> Result<Inode> {
+        self.unbind(path).ok_or(Error::NotFound)
+    }
+
+    fn getxattr(&'_ mut self, _req: &mut Request, name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        self.acls.getxattr(&self.attr.inner, name)
+    }
+    fn setxattr(&mut self, req: &mut Request, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        self.acls.setxattr(req, &mut self.attr.inner, name, value, flags)
+    }
+    fn listxattr(&mut self, _req: &mut Request, output: &mut Vec<OsString>) -> Result<()> {
+        self.acls.listxattr(output);
         Ok(())
     }
+    fn removexattr(&mut self, req: &mut Request, name: &OsStr) -> Result<()> {
+        self.acls.removexattr(req, &self.attr.inner, name)
+    }
 }
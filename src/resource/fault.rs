@@ -0,0 +1,348 @@
+//! A resource wrapper that injects faults, for testing how clients
+//! handle flaky storage
+
+use crate::{
+    returns,
+    Error,
+    FileHandle,
+    Inode,
+    OpenFlags,
+    Request,
+    Resource,
+    Result,
+    XattrFlags,
+};
+
+use std::{
+    borrow::Cow,
+    cmp,
+    convert::TryFrom,
+    ffi::{OsStr, OsString},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use libc::c_int;
+
+/// What happens when a rule triggers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Fail the operation with this errno, like `EIO` or `ENOSPC`
+    Errno(c_int),
+    /// Return at most this many bytes from `read`. Has no effect on
+    /// other operations.
+    ShortRead(u32),
+    /// Sleep before forwarding the operation. Note that this blocks
+    /// the whole filesystem, not just this resource.
+    Delay(Duration),
+}
+
+/// Which of the matching calls a rule triggers on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Every matching call
+    Always,
+    /// Every nth matching call, starting with the nth
+    EveryNth(u64),
+    /// Each matching call with this probability, drawn from the
+    /// injector's seeded generator
+    Probability(f64),
+}
+
+/// A single fault and when to inject it
+#[derive(Debug, Clone, Copy)]
+pub struct Rule {
+    operation: Option<&'static str>,
+    inode: Option<Inode>,
+    trigger: Trigger,
+    fault: Fault,
+    calls: u64,
+}
+impl Rule {
+    /// Create a rule that injects `fault` on every call
+    pub fn new(fault: Fault) -> Self {
+        Self {
+            operation: None,
+            inode: None,
+            trigger: Trigger::Always,
+            fault,
+            calls: 0,
+        }
+    }
+    /// A chaining function to only match one operation, named like
+    /// the `Resource` method, such as `"read"`. Both `readdir` and
+    /// `readdir_from` count as `"readdir"`.
+    pub fn with_operation(mut self, operation: &'static str) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+    /// A chaining function to only match calls on one inode, for
+    /// injectors shared between several inodes
+    pub fn with_inode(mut self, inode: Inode) -> Self {
+        self.inode = Some(inode);
+        self
+    }
+    /// A chaining function to set when the rule triggers
+    pub fn with_trigger(mut self, trigger: Trigger) -> Self {
+        self.trigger = trigger;
+        self
+    }
+
+    fn matches(&self, operation: &str, inode: Inode) -> bool {
+        self.operation.map_or(true, |op| op == operation)
+            && self.inode.map_or(true, |ino| ino == inode)
+    }
+}
+
+/// Wraps a resource and injects faults into its operations according
+/// to a list of rules. Rules are checked in order, and every triggered
+/// one is applied: delays add up, the shortest read wins, and the
+/// first error stops the operation. Random triggers use a generator
+/// seeded at creation, so a run can be reproduced. Calls `EasyFuse`
+/// makes only to check permissions, such as reading the ACL before
+/// `open`, are neither injected into nor counted, see
+/// `Request::is_permission_check`.
+#[derive(Debug)]
+pub struct FaultInjector<R> {
+    inner: R,
+    rules: Vec<Rule>,
+    state: u64,
+}
+impl<R> FaultInjector<R>
+where
+    R: Resource
+{
+    /// Wrap `inner`, seeding the random generator with `seed`
+    pub fn new(inner: R, seed: u64) -> Self {
+        Self {
+            inner,
+            rules: Vec::new(),
+            state: seed,
+        }
+    }
+    /// A chaining function to add a rule
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.add_rule(rule);
+        self
+    }
+    /// Add a rule, checked after all previous ones
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+    /// Remove all rules, making this a plain passthrough
+    pub fn clear_rules(&mut self) {
+        self.rules.clear();
+    }
+
+    /// Getter for the wrapped resource
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+    /// Mutable getter for the wrapped resource
+    pub fn inner_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+    /// Unwrap the inner resource
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// The next value of a splitmix64 generator
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    /// A random number in `0.0..1.0`
+    #[allow(clippy::cast_precision_loss)]
+    fn next_unit(&mut self) -> f64 {
+        (self.next_random() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// Apply all rules triggering on this call, returning the read
+    /// limit if any
+    fn inject(&mut self, operation: &str, req: &Request) -> Result<Option<u32>> {
+        if req.is_permission_check() {
+            return Ok(None);
+        }
+        let mut limit = None;
+        for i in 0..self.rules.len() {
+            if !self.rules[i].matches(operation, req.inode) {
+                continue;
+            }
+            let rule = &mut self.rules[i];
+            rule.calls = rule.calls.saturating_add(1);
+            let triggered = match rule.trigger {
+                Trigger::Always => true,
+                Trigger::EveryNth(n) => n != 0 && rule.calls.checked_rem(n) == Some(0),
+                Trigger::Probability(p) => self.next_unit() < p,
+            };
+            if !triggered {
+                continue;
+            }
+
+            match self.rules[i].fault {
+                Fault::Errno(errno) => return Err(Error::Errno(errno)),
+                Fault::ShortRead(len) => limit = Some(limit.map_or(len, |limit| cmp::min(limit, len))),
+                Fault::Delay(delay) => thread::sleep(delay),
+            }
+        }
+        Ok(limit)
+    }
+}
+
+impl<R> Resource for FaultInjector<R>
+where
+    R: Resource
+{
+    fn getattr(&mut self, req: &mut Request) -> Result<returns::Attr> {
+        self.inject("getattr", req)?;
+        self.inner.getattr(req)
+    }
+
+    // Directory operations
+
+    fn opendir(&mut self, req: &mut Request, flags: u32) -> Result<FileHandle> {
+        self.inject("opendir", req)?;
+        self.inner.opendir(req, flags)
+    }
+    fn releasedir(&mut self, req: &mut Request, fh: FileHandle, flags: u32) -> Result<()> {
+        self.inject("releasedir", req)?;
+        self.inner.releasedir(req, fh, flags)
+    }
+    fn lookup(&mut self, req: &mut Request, path: &OsStr) -> Result<returns::Entry> {
+        self.inject("lookup", req)?;
+        self.inner.lookup(req, path)
+    }
+    fn readdir(&mut self, req: &mut Request, fh: FileHandle, output: &mut Vec<returns::DirEntry>) -> Result<()> {
+        self.inject("readdir", req)?;
+        self.inner.readdir(req, fh, output)
+    }
+    fn readdir_from(&mut self, req: &mut Request, fh: FileHandle, offset: i64, output: &mut returns::DirWriter) -> Result<()> {
+        self.inject("readdir", req)?;
+        self.inner.readdir_from(req, fh, offset, output)
+    }
+    fn symlink(&'_ mut self, req: &mut Request, path: &OsStr, link: &Path) -> Result<returns::Entry> {
+        self.inject("symlink", req)?;
+        self.inner.symlink(req, path, link)
+    }
+    fn link(&mut self, req: &mut Request, path: &OsStr, inode: Inode) -> Result<()> {
+        self.inject("link", req)?;
+        self.inner.link(req, path, inode)
+    }
+    fn unlink(&mut self, req: &mut Request, path: &OsStr) -> Result<Inode> {
+        self.inject("unlink", req)?;
+        self.inner.unlink(req, path)
+    }
+
+    // File operations
+
+    fn open(&mut self, req: &mut Request, flags: OpenFlags) -> Result<returns::Opened> {
+        self.inject("open", req)?;
+        self.inner.open(req, flags)
+    }
+    fn close(&mut self, req: &mut Request, fh: FileHandle, flags: OpenFlags) -> Result<()> {
+        self.inject("close", req)?;
+        self.inner.close(req, fh, flags)
+    }
+    fn read(&'_ mut self, req: &mut Request, fh: FileHandle, offset: i64, len: u32) -> Result<Cow<'_, [u8]>> {
+        let limit = self.inject("read", req)?;
+        let data = self.inner.read(req, fh, offset, len)?;
+        let limit = match limit.and_then(|limit| usize::try_from(limit).ok()) {
+            Some(limit) if limit < data.len() => limit,
+            _ => return Ok(data),
+        };
+        Ok(match data {
+            Cow::Borrowed(data) => Cow::Borrowed(&data[..limit]),
+            Cow::Owned(mut data) => {
+                data.truncate(limit);
+                Cow::Owned(data)
+            },
+        })
+    }
+
+    // Xattr operations
+
+    fn getxattr(&'_ mut self, req: &mut Request, name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        self.inject("getxattr", req)?;
+        self.inner.getxattr(req, name)
+    }
+    fn setxattr(&mut self, req: &mut Request, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        self.inject("setxattr", req)?;
+        self.inner.setxattr(req, name, value, flags)
+    }
+    fn listxattr(&mut self, req: &mut Request, output: &mut Vec<OsString>) -> Result<()> {
+        self.inject("listxattr", req)?;
+        self.inner.listxattr(req, output)
+    }
+    fn removexattr(&mut self, req: &mut Request, name: &OsStr) -> Result<()> {
+        self.inject("removexattr", req)?;
+        self.inner.removexattr(req, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EasyFuse;
+
+    struct Nothing;
+    impl Resource for Nothing {}
+
+    /// Which of `calls` calls to `operation` fail
+    fn failures(injector: &mut FaultInjector<Nothing>, operation: &str, calls: usize) -> Vec<bool> {
+        let mut fs = EasyFuse::default();
+        let req = fs.internal_request(Inode(2));
+        (0..calls).map(|_| injector.inject(operation, &req).is_err()).collect()
+    }
+
+    #[test]
+    fn seeded_sequence() {
+        // The reference splitmix64 outputs for a zero seed
+        let mut injector = FaultInjector::new(Nothing, 0);
+        assert_eq!(injector.next_random(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(injector.next_random(), 0x6E78_9E6A_A1B9_65F4);
+    }
+    #[test]
+    fn every_nth() {
+        let rule = Rule::new(Fault::Errno(libc::EIO)).with_operation("read");
+        let mut injector = FaultInjector::new(Nothing, 0)
+            .with_rule(rule.with_trigger(Trigger::EveryNth(2)));
+        assert_eq!(failures(&mut injector, "read", 4), [false, true, false, true]);
+        // Other operations neither fail nor count
+        assert_eq!(failures(&mut injector, "open", 2), [false, false]);
+        assert_eq!(failures(&mut injector, "read", 2), [false, true]);
+
+        let mut injector = FaultInjector::new(Nothing, 0)
+            .with_rule(rule.with_trigger(Trigger::EveryNth(0)));
+        assert_eq!(failures(&mut injector, "read", 2), [false, false]);
+    }
+    #[test]
+    fn probability() {
+        let rule = Rule::new(Fault::Errno(libc::EIO));
+        let mut injector = FaultInjector::new(Nothing, 42)
+            .with_rule(rule.with_trigger(Trigger::Probability(0.5)));
+        let pattern = failures(&mut injector, "read", 8);
+        assert_eq!(pattern, [false, true, true, true, true, false, true, false]);
+
+        let mut injector = FaultInjector::new(Nothing, 42)
+            .with_rule(rule.with_trigger(Trigger::Probability(0.0)))
+            .with_rule(rule.with_trigger(Trigger::Probability(1.0)).with_operation("open"));
+        assert_eq!(failures(&mut injector, "read", 4), [false; 4]);
+        assert_eq!(failures(&mut injector, "open", 4), [true; 4]);
+    }
+    #[test]
+    fn permission_checks_pass_through() {
+        let mut injector = FaultInjector::new(Nothing, 0)
+            .with_rule(Rule::new(Fault::Errno(libc::EIO)).with_trigger(Trigger::EveryNth(2)));
+        let mut fs = EasyFuse::default();
+        let mut req = fs.internal_request(Inode(2));
+        assert!(req.check_permissions(|req| injector.inject("getxattr", req)).is_ok());
+        assert!(injector.inject("open", &req).is_ok());
+        assert!(req.check_permissions(|req| injector.inject("getattr", req)).is_ok());
+        assert!(injector.inject("open", &req).is_err());
+    }
+}
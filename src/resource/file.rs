@@ -1,21 +1,28 @@
 //! Different `Resource` implementations for file-like nodes
 
 use crate::{
+    acl::AclXattrs,
+    blocks_for,
     returns,
+    Error,
     File,
     FileHandle,
+    OpenFlags,
     Permissions,
     Request,
     Result,
+    XattrFlags,
 };
 
 use std::{
     borrow::Cow,
     cmp,
     convert::TryInto,
+    ffi::{OsStr, OsString},
+    fmt,
 };
 
-use fuse::FileType;
+use fuser::FileType;
 
 /// A simple static file
 #[allow(clippy::module_name_repetitions)]
@@ -23,6 +30,7 @@ use fuse::FileType;
 pub struct StaticFile {
     content: Vec<u8>,
     attr: returns::Attr,
+    acls: AclXattrs,
 }
 impl StaticFile {
     /// Create a new instance from a file attribute
@@ -30,6 +38,7 @@ impl StaticFile {
         Self {
             content: Vec::default(),
             attr,
+            acls: AclXattrs::new(),
         }
     }
 
@@ -45,6 +54,15 @@ impl StaticFile {
         self.attr = attr.into();
     }
 
+    /// Getter for the stored ACLs
+    pub fn acls(&self) -> &AclXattrs {
+        &self.acls
+    }
+    /// Mutable getter for the stored ACLs
+    pub fn acls_mut(&mut self) -> &mut AclXattrs {
+        &mut self.acls
+    }
+
     /// Set the static data to be read from the file
     pub fn set_content<C>(&mut self, content: C)
     where
@@ -58,25 +76,141 @@ impl StaticFile {
     }
 }
 impl File for StaticFile {
-    #[allow(clippy::integer_arithmetic)] // not dividing by zero, clippy ya dumb fuck
-    #[allow(clippy::integer_division)]   // i am very much aware of that this will truncate
     fn getattr(&mut self, _req: &mut Request) -> Result<returns::Attr> {
         // Save the user from himself
         self.attr.inner.kind = FileType::RegularFile;
         self.attr.inner.size = self.content.len().try_into().unwrap_or(u64::max_value());
-        self.attr.inner.blocks = self.attr.inner.size / 4096;
+        self.attr.inner.blocks = blocks_for(self.attr.inner.size);
         Ok(self.attr)
     }
 
     fn read(&'_ mut self, req: &mut Request, _fh: FileHandle, offset: i64, len: u32) -> Result<Cow<'_, [u8]>> {
-        req.ensure_access(&self.attr.inner, Permissions::READ)?;
+        req.ensure_access_acl(&self.attr.inner, self.acls.access_acl(), Permissions::READ)?;
         let start: usize = offset.try_into().unwrap_or(0);
         let end: usize = cmp::min(
             len.try_into().ok().and_then(|len| start.checked_add(len)).expect("integer overflow"),
             self.content.len()
         );
 
-        let buf = &self.content.get(start..end).ok_or(libc::ERANGE)?;
+        let buf = &self.content.get(start..end).ok_or(Error::Errno(libc::ERANGE))?;
         Ok(Cow::Borrowed(&buf))
     }
+
+    fn getxattr(&'_ mut self, _req: &mut Request, name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        self.acls.getxattr(&self.attr.inner, name)
+    }
+    fn setxattr(&mut self, req: &mut Request, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        self.acls.setxattr(req, &mut self.attr.inner, name, value, flags)
+    }
+    fn listxattr(&mut self, _req: &mut Request, output: &mut Vec<OsString>) -> Result<()> {
+        self.acls.listxattr(output);
+        Ok(())
+    }
+    fn removexattr(&mut self, req: &mut Request, name: &OsStr) -> Result<()> {
+        self.acls.removexattr(req, &self.attr.inner, name)
+    }
+}
+
+/// A file whose content is generated by a function every time it's
+/// read, like the files in `/proc`. Since the size isn't known up
+/// front, it's opened with direct I/O by default so the kernel
+/// doesn't stop reading at the `size` attribute.
+#[allow(clippy::module_name_repetitions)]
+pub struct FnFile<F> {
+    generate: F,
+    attr: returns::Attr,
+    acls: AclXattrs,
+    flags: returns::OpenedFlags,
+}
+impl<F> FnFile<F>
+where
+    F: FnMut(&mut Request) -> Result<Vec<u8>>
+{
+    /// Create a new instance from a file attribute and a function
+    /// returning the full content of the file
+    pub fn new(attr: returns::Attr, generate: F) -> Self {
+        Self {
+            generate,
+            attr,
+            acls: AclXattrs::new(),
+            flags: returns::OpenedFlags::DIRECT_IO,
+        }
+    }
+
+    /// Getter for the inner file attributes
+    pub fn attr(&self) -> &returns::Attr {
+        &self.attr
+    }
+    /// Setter for the inner file attributes
+    pub fn set_attr<T>(&mut self, attr: T)
+    where
+        T: Into<returns::Attr>
+    {
+        self.attr = attr.into();
+    }
+
+    /// Getter for the stored ACLs
+    pub fn acls(&self) -> &AclXattrs {
+        &self.acls
+    }
+    /// Mutable getter for the stored ACLs
+    pub fn acls_mut(&mut self) -> &mut AclXattrs {
+        &mut self.acls
+    }
+
+    /// Set the flags this file is opened with, which defaults to
+    /// `DIRECT_IO`
+    pub fn set_opened_flags(&mut self, flags: returns::OpenedFlags) {
+        self.flags = flags;
+    }
+}
+impl<F> File for FnFile<F>
+where
+    F: FnMut(&mut Request) -> Result<Vec<u8>>
+{
+    fn getattr(&mut self, _req: &mut Request) -> Result<returns::Attr> {
+        // Save the user from himself
+        self.attr.inner.kind = FileType::RegularFile;
+        Ok(self.attr)
+    }
+
+    fn open(&mut self, _req: &mut Request, _flags: OpenFlags) -> Result<returns::Opened> {
+        Ok(returns::Opened::from(FileHandle(0)).with_flags(self.flags))
+    }
+
+    fn read(&'_ mut self, req: &mut Request, _fh: FileHandle, offset: i64, len: u32) -> Result<Cow<'_, [u8]>> {
+        req.ensure_access_acl(&self.attr.inner, self.acls.access_acl(), Permissions::READ)?;
+        let mut content = (self.generate)(req)?;
+        let start = cmp::min(offset.try_into().unwrap_or(0), content.len());
+        let end = len.try_into().ok()
+            .and_then(|len| start.checked_add(len))
+            .map_or(content.len(), |end| cmp::min(end, content.len()));
+
+        content.truncate(end);
+        content.drain(..start);
+        Ok(Cow::Owned(content))
+    }
+
+    fn getxattr(&'_ mut self, _req: &mut Request, name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        self.acls.getxattr(&self.attr.inner, name)
+    }
+    fn setxattr(&mut self, req: &mut Request, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        self.acls.setxattr(req, &mut self.attr.inner, name, value, flags)
+    }
+    fn listxattr(&mut self, _req: &mut Request, output: &mut Vec<OsString>) -> Result<()> {
+        self.acls.listxattr(output);
+        Ok(())
+    }
+    fn removexattr(&mut self, req: &mut Request, name: &OsStr) -> Result<()> {
+        self.acls.removexattr(req, &self.attr.inner, name)
+    }
+}
+impl<F> fmt::Debug for FnFile<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FnFile")
+            .field("attr", &self.attr)
+            .field("acls", &self.acls)
+            .field("flags", &self.flags)
+            .finish()
+    }
 }
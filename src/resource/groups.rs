@@ -0,0 +1,60 @@
+//! Lookup of the supplementary groups of calling processes, which
+//! FUSE doesn't pass along with the request
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryFrom,
+    fs,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// How long the groups of a process are trusted. A process can change
+/// its groups, and its pid can be reused, so don't keep them forever.
+const GROUPS_TTL: Duration = Duration::from_secs(1);
+/// Expired entries are only swept once there are this many
+const SWEEP_THRESHOLD: usize = 256;
+
+/// A cache of supplementary groups by pid
+#[derive(Debug, Default)]
+pub(crate) struct GroupCache {
+    groups: RefCell<HashMap<u32, (Instant, Rc<[u32]>)>>,
+}
+impl GroupCache {
+    /// The supplementary groups of `pid`, read from `/proc` unless
+    /// they were read recently. Empty if the process is gone or
+    /// there's no `/proc` to read from.
+    pub(crate) fn get(&self, pid: u32) -> Rc<[u32]> {
+        let now = Instant::now();
+        let mut cache = self.groups.borrow_mut();
+        if let Some((read_at, groups)) = cache.get(&pid) {
+            if now.duration_since(*read_at) < GROUPS_TTL {
+                return Rc::clone(groups);
+            }
+        }
+
+        if cache.len() >= SWEEP_THRESHOLD {
+            cache.retain(|_, (read_at, _)| now.duration_since(*read_at) < GROUPS_TTL);
+        }
+        let groups: Rc<[u32]> = read_groups(pid).unwrap_or_default().into();
+        cache.insert(pid, (now, Rc::clone(&groups)));
+        groups
+    }
+}
+
+/// Parse the `Groups:` line of `/proc/<pid>/status`
+fn read_groups(pid: u32) -> Option<Vec<u32>> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find_map(|line| line.strip_prefix("Groups:"))?;
+    Some(line.split_whitespace().filter_map(|gid| gid.parse().ok()).collect())
+}
+
+/// The supplementary groups of the current process
+pub(crate) fn own_groups() -> Rc<[u32]> {
+    let len = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; usize::try_from(len).unwrap_or(0)];
+    let len = unsafe { libc::getgroups(len, groups.as_mut_ptr()) };
+    groups.truncate(usize::try_from(len).unwrap_or(0));
+    groups.into()
+}
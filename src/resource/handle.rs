@@ -0,0 +1,225 @@
+//! A table of per-open state, for resources that need to tell their
+//! open instances apart
+
+use crate::{
+    returns,
+    Error,
+    File,
+    FileHandle,
+    OpenFlags,
+    Request,
+    Result,
+    XattrFlags,
+};
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fmt,
+};
+
+/// Allocates file handles and stores a value for each of them, to be
+/// looked up again in `read` and removed in `close`. `Stateful` uses
+/// one of these for `StatefulFile::Handle`, but it can be used
+/// directly from any resource.
+pub struct HandleTable<T> {
+    handles: HashMap<u64, T>,
+    next: u64,
+}
+impl<T> HandleTable<T> {
+    /// Create a new empty table
+    pub fn new() -> Self {
+        Self {
+            handles: HashMap::new(),
+            next: 1,
+        }
+    }
+
+    /// Store `value` under a new file handle
+    pub fn insert(&mut self, value: T) -> FileHandle {
+        while self.handles.contains_key(&self.next) {
+            self.next = self.next.wrapping_add(1);
+        }
+        let fh = self.next;
+        self.next = fh.wrapping_add(1);
+        self.handles.insert(fh, value);
+        FileHandle(fh)
+    }
+    /// Get the value of a file handle, or `EBADF` if there is none
+    pub fn get(&self, fh: FileHandle) -> Result<&T> {
+        self.handles.get(&fh.0).ok_or(Error::Errno(libc::EBADF))
+    }
+    /// Get the value of a file handle mutably, or `EBADF` if there is
+    /// none
+    pub fn get_mut(&mut self, fh: FileHandle) -> Result<&mut T> {
+        self.handles.get_mut(&fh.0).ok_or(Error::Errno(libc::EBADF))
+    }
+    /// Remove a file handle and return its value, or `EBADF` if there
+    /// is none
+    pub fn remove(&mut self, fh: FileHandle) -> Result<T> {
+        self.handles.remove(&fh.0).ok_or(Error::Errno(libc::EBADF))
+    }
+
+    /// The number of open handles
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+    /// Returns true if there are no open handles
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
+impl<T> Default for HandleTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> fmt::Debug for HandleTable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandleTable")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Like `File`, but with some state kept for each open instance of
+/// the file, such as a cursor or a buffer. Wrap it in `Stateful` to
+/// get a `File`, which in turn can be wrapped in `FileResource`.
+pub trait StatefulFile {
+    /// The state of one open instance
+    type Handle;
+
+    /// See `File::getattr`
+    fn getattr(&mut self, _req: &mut Request) -> Result<returns::Attr>;
+    /// See `Resource::open`, except this returns the state of the new
+    /// open instance rather than a raw file handle
+    fn open(&mut self, _req: &mut Request, _flags: OpenFlags) -> Result<returns::Opened<Self::Handle>>;
+    /// See `Resource::close`. The handle is dropped afterwards.
+    fn close(&mut self, _req: &mut Request, _handle: Self::Handle, _flags: OpenFlags) -> Result<()> {
+        Ok(())
+    }
+    /// See `Resource::read`
+    fn read(&'_ mut self, _req: &mut Request, _handle: &mut Self::Handle, _offset: i64, _len: u32) -> Result<Cow<'_, [u8]>> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::getxattr`
+    fn getxattr(&'_ mut self, _req: &mut Request, _name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::setxattr`
+    fn setxattr(&mut self, _req: &mut Request, _name: &OsStr, _value: &[u8], _flags: XattrFlags) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::listxattr`
+    fn listxattr(&mut self, _req: &mut Request, _output: &mut Vec<OsString>) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::removexattr`
+    fn removexattr(&mut self, _req: &mut Request, _name: &OsStr) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+}
+
+/// Turns a `StatefulFile` into a `File` by keeping its handles in a
+/// `HandleTable`
+#[derive(Debug)]
+pub struct Stateful<F: StatefulFile>(pub F, HandleTable<F::Handle>);
+
+impl<F: StatefulFile> Stateful<F> {
+    /// Create a new instance with no open handles
+    pub fn new(file: F) -> Self {
+        Self(file, HandleTable::new())
+    }
+}
+impl<F: StatefulFile> From<F> for Stateful<F> {
+    fn from(file: F) -> Self {
+        Self::new(file)
+    }
+}
+
+impl<F: StatefulFile> File for Stateful<F> {
+    fn getattr(&mut self, req: &mut Request) -> Result<returns::Attr> {
+        self.0.getattr(req)
+    }
+
+    fn open(&mut self, req: &mut Request, flags: OpenFlags) -> Result<returns::Opened> {
+        let opened = self.0.open(req, flags)?;
+        Ok(returns::Opened::from(self.1.insert(opened.handle)).with_flags(opened.flags))
+    }
+    fn close(&mut self, req: &mut Request, fh: FileHandle, flags: OpenFlags) -> Result<()> {
+        let handle = self.1.remove(fh)?;
+        self.0.close(req, handle, flags)
+    }
+    fn read(&'_ mut self, req: &mut Request, fh: FileHandle, offset: i64, len: u32) -> Result<Cow<'_, [u8]>> {
+        let handle = self.1.get_mut(fh)?;
+        self.0.read(req, handle, offset, len)
+    }
+
+    fn getxattr(&'_ mut self, req: &mut Request, name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        self.0.getxattr(req, name)
+    }
+    fn setxattr(&mut self, req: &mut Request, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        self.0.setxattr(req, name, value, flags)
+    }
+    fn listxattr(&mut self, req: &mut Request, output: &mut Vec<OsString>) -> Result<()> {
+        self.0.listxattr(req, output)
+    }
+    fn removexattr(&mut self, req: &mut Request, name: &OsStr) -> Result<()> {
+        self.0.removexattr(req, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AttrBuilder, EasyFuse, Inode};
+    use std::rc::Rc;
+
+    #[test]
+    fn missing_handles_are_ebadf() {
+        let mut table = HandleTable::new();
+        let a = table.insert("a");
+        let b = table.insert("b");
+        assert_ne!(a, b);
+        *table.get_mut(a).unwrap() = "c";
+        assert_eq!(table.get(a).unwrap(), &"c");
+
+        assert_eq!(table.remove(a).unwrap(), "c");
+        assert_eq!(table.get(a).unwrap_err().errno(), libc::EBADF);
+        assert_eq!(table.get_mut(a).unwrap_err().errno(), libc::EBADF);
+        assert_eq!(table.remove(a).unwrap_err().errno(), libc::EBADF);
+        assert_eq!(table.len(), 1);
+    }
+
+    /// Hands out clones of one `Rc`, to count the live handles
+    struct Shared(Rc<()>);
+    impl StatefulFile for Shared {
+        type Handle = Rc<()>;
+
+        fn getattr(&mut self, _req: &mut Request) -> Result<returns::Attr> {
+            Ok(AttrBuilder::file().build())
+        }
+        fn open(&mut self, _req: &mut Request, _flags: OpenFlags) -> Result<returns::Opened<Self::Handle>> {
+            Ok(Rc::clone(&self.0).into())
+        }
+    }
+
+    #[test]
+    fn state_dropped_on_close() {
+        let shared = Rc::new(());
+        let mut file = Stateful::new(Shared(Rc::clone(&shared)));
+        let mut fs = EasyFuse::default();
+        let mut req = fs.internal_request(Inode(2));
+
+        let a = file.open(&mut req, OpenFlags::empty()).unwrap().handle;
+        let b = file.open(&mut req, OpenFlags::empty()).unwrap().handle;
+        assert_eq!(Rc::strong_count(&shared), 4);
+        file.close(&mut req, a, OpenFlags::empty()).unwrap();
+        assert_eq!(Rc::strong_count(&shared), 3);
+        assert_eq!(file.close(&mut req, a, OpenFlags::empty()).unwrap_err().errno(), libc::EBADF);
+        file.close(&mut req, b, OpenFlags::empty()).unwrap();
+        assert_eq!(Rc::strong_count(&shared), 2);
+        assert!(file.1.is_empty());
+    }
+}
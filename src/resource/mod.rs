@@ -2,44 +2,121 @@
 //! filesystem node. `EasyFuse` is built upon different generic
 //! resources being combined together to form a filesystem.
 
-use crate::{returns, EasyFuse, Result};
+use crate::{acl::Acl, returns, unwind, EasyFuse, Error, Result};
 
 use std::{
     borrow::Cow,
     convert::TryFrom,
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
     path::Path,
+    rc::Rc,
 };
 
-use fuse::FileAttr;
+use fuser::{FileAttr, FileType};
 
 pub mod attr;
 pub mod newtypes;
 pub mod dir;
+pub mod fault;
 pub mod file;
+pub(crate) mod groups;
+pub mod handle;
+pub mod pathfs;
 
 pub use attr::*;
+pub use handle::*;
 pub use newtypes::*;
+pub use pathfs::*;
 
 /// Data common for all request types
 #[derive(Debug)]
 pub struct Request<'a> {
-    /// The inner FUSE request parameters
-    pub inner: &'a fuse::Request<'a>,
+    /// The inner FUSE request parameters, or `None` if this request
+    /// was made internally by easyfuse, such as when resolving a path
+    /// from application code.
+    pub inner: Option<&'a fuser::Request<'a>>,
     /// The core file system, which has the possibility to lookup
-    /// resources by inodes or register new resources.
+    /// resources by inodes or register new resources. See the `cell`
+    /// module for how to safely borrow other resources from here.
     pub fs: &'a mut EasyFuse,
     /// The inode of the current resource.
     pub inode: Inode,
+    /// The access ACL of the current resource, if `EasyFuse` looked it
+    /// up for this operation
+    pub(crate) acl: Option<Acl>,
+    /// Whether this is `EasyFuse` checking permissions, see
+    /// `is_permission_check`
+    pub(crate) checking: bool,
 }
 impl<'a> Request<'a> {
+    /// The user ID of the calling process. Internal requests act as
+    /// the user running the filesystem.
+    pub fn uid(&self) -> u32 {
+        self.inner.map_or_else(|| unsafe { libc::getuid() }, |req| req.uid())
+    }
+    /// The group ID of the calling process. Internal requests act as
+    /// the group running the filesystem.
+    pub fn gid(&self) -> u32 {
+        self.inner.map_or_else(|| unsafe { libc::getgid() }, |req| req.gid())
+    }
+    /// The process ID of the calling process. Internal requests act
+    /// as the process running the filesystem.
+    pub fn pid(&self) -> u32 {
+        self.inner.map_or_else(std::process::id, |req| req.pid())
+    }
+
+    /// Call into the resource at `inode` through `f`, catching a panic
+    /// like `EasyFuse` does for the resource it dispatches to. This
+    /// way a panicking resource poisons itself instead of the one
+    /// calling it, see `EasyFuse::set_poison_on_panic`. Fails with
+    /// `EIO` if `inode` is already poisoned.
+    pub fn call<T, F>(&mut self, inode: Inode, operation: &'static str, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>
+    {
+        if self.fs.is_poisoned(inode) {
+            return Err(Error::Errno(libc::EIO));
+        }
+        match unwind::catch(operation, || f(self)) {
+            Some(result) => result,
+            None => {
+                self.fs.poison(inode, operation);
+                Err(Error::Errno(libc::EIO))
+            },
+        }
+    }
+
+    /// The supplementary groups of the calling process. These are
+    /// read from `/proc` and cached for a short while, since FUSE
+    /// doesn't pass them along.
+    pub fn groups(&self) -> Rc<[u32]> {
+        match self.inner {
+            Some(req) => self.fs.groups.get(req.pid()),
+            None => groups::own_groups(),
+        }
+    }
+    /// Returns true if the calling process is in `gid`, either as its
+    /// primary group or as a supplementary one
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid() == gid || self.groups().contains(&gid)
+    }
+
     /// Return the relevant permission digit when the current user
-    /// tries to open a specific file.
+    /// tries to open a specific file. Root may read and write
+    /// anything, and execute anything that's executable by someone.
     #[allow(clippy::integer_arithmetic)] // clippy is dumb
     pub fn perms(&self, attrs: &FileAttr) -> Permissions {
-        let perms = if self.inner.uid() == attrs.uid {
+        if self.uid() == 0 {
+            let mut perms = Permissions::READ | Permissions::WRITE;
+            if attrs.kind == FileType::Directory || attrs.perm & 0o111 != 0 {
+                perms |= Permissions::EXECUTE;
+            }
+            return perms;
+        }
+
+        let perms = if self.uid() == attrs.uid {
             (attrs.perm & 0o700) >> (3*2)
-        } else if self.inner.gid() == attrs.gid {
+        } else if self.in_group(attrs.gid) {
             (attrs.perm & 0o070) >> 3
         } else {
             (attrs.perm & 0o007)
@@ -49,13 +126,48 @@ impl<'a> Request<'a> {
             .expect("Permission was not shifted correctly")
     }
 
-    /// Compare the user permissions using `perms` and raise an
-    /// `EPERM` if it's lacking.
+    /// Returns true if `EasyFuse` is only calling the resource to check
+    /// permissions for the actual operation, such as the `getattr`
+    /// before `open` or the `getxattr` for `access_acl`. Wrappers like
+    /// `FaultInjector` may want to let these through untouched.
+    pub fn is_permission_check(&self) -> bool {
+        self.checking
+    }
+    /// Run `f` as a permission check, see `is_permission_check`
+    pub(crate) fn check_permissions<T, F>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut Self) -> T
+    {
+        let checking = std::mem::replace(&mut self.checking, true);
+        let result = f(self);
+        self.checking = checking;
+        result
+    }
+    /// The access ACL of the current resource. `EasyFuse` reads it
+    /// through `getxattr` before operations that may check access,
+    /// which are `lookup`, `opendir`, `readdir`, `symlink`, `link`,
+    /// `unlink`, `open` and `read`. It's `None` for other operations,
+    /// for internal requests, or if the resource has no ACL.
+    pub fn access_acl(&self) -> Option<&Acl> {
+        self.acl.as_ref()
+    }
+    /// Compare the user permissions against `access_acl`, or using
+    /// `perms` if there is none, and raise an `EACCES` if they're
+    /// lacking.
     pub fn ensure_access(&self, attrs: &FileAttr, required: Permissions) -> Result<()> {
-        if self.perms(attrs).contains(required) {
+        self.ensure_access_acl(attrs, self.access_acl(), required)
+    }
+    /// Like `ensure_access`, but evaluates `acl` instead of the mode
+    /// if there is one. See `Acl::permits`.
+    pub fn ensure_access_acl(&self, attrs: &FileAttr, acl: Option<&Acl>, required: Permissions) -> Result<()> {
+        let permitted = match acl {
+            Some(acl) => acl.permits(self, attrs, required),
+            None => self.perms(attrs).contains(required),
+        };
+        if permitted {
             Ok(())
         } else {
-            Err(libc::EPERM)
+            Err(Error::PermissionDenied)
         }
     }
 }
@@ -64,11 +176,16 @@ impl<'a> Request<'a> {
 /// be linked to a resource to make all filesystem operations on that
 /// inode get passed to here.
 pub trait Resource {
+    /// The name of this resource's type, used in logs and traces
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
     /// Get meta information of this file, for example when the `stat`
     /// system call is made. The `ino` value returned here will be
     /// overwritten with this resource's inode, so set it to zero.
     fn getattr(&mut self, _req: &mut Request) -> Result<returns::Attr> {
-        Err(libc::ENOSYS)
+        Err(Error::Errno(libc::ENOSYS))
     }
 
     //  ____  _                                   _   _
@@ -78,20 +195,57 @@ pub trait Resource {
     // |____/|_|_|     \___/| .__/ \___|_|  \__,_|\__|_|\___/|_| |_|___/
     //                      |_|
 
+    /// Open this resource for listing, assuming it's a directory.
+    /// Like `open`, the returned handle means nothing to anyone but
+    /// this resource, and is passed to `readdir` and `releasedir`.
+    /// This is a good place to check permissions or to take a
+    /// snapshot of the entries for this listing.
+    fn opendir(&mut self, _req: &mut Request, _flags: u32) -> Result<FileHandle> {
+        Ok(FileHandle(0))
+    }
+    /// Close a handle returned from `opendir`, assuming it's a
+    /// directory. Any errors are ignored by FUSE.
+    fn releasedir(&mut self, _req: &mut Request, _fh: FileHandle, _flags: u32) -> Result<()> {
+        Ok(())
+    }
     /// Convert a path to a child of this resource to an inode,
     /// assuming it's a directory
     fn lookup(&mut self, _req: &mut Request, _path: &OsStr) -> Result<returns::Entry> {
-        Err(libc::ENOSYS)
+        Err(Error::Errno(libc::ENOSYS))
     }
     /// Read all entries of this resource, assuming it's a directory
-    fn readdir(&mut self, _req: &mut Request, _output: &mut Vec<returns::DirEntry>) -> Result<()> {
-        Err(libc::ENOSYS)
+    fn readdir(&mut self, _req: &mut Request, _fh: FileHandle, _output: &mut Vec<returns::DirEntry>) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// Read entries of this resource starting right after the entry
+    /// whose `offset` cookie is `offset` (or from the beginning if
+    /// it's zero), assuming it's a directory. Stop as soon as
+    /// `output.push` returns false. The default implementation calls
+    /// `readdir` and numbers the entries by position, which is
+    /// neither efficient nor stable if the directory changes while
+    /// it's being listed.
+    fn readdir_from(&mut self, req: &mut Request, fh: FileHandle, offset: i64, output: &mut returns::DirWriter) -> Result<()> {
+        let mut entries = Vec::new();
+        self.readdir(req, fh, &mut entries)?;
+        output.push_positional(entries, offset)
     }
     /// Symlink a file into this resource, assuming it's a
     /// directory. Should return the stat for the created symlink,
     /// similar to `lookup`.
     fn symlink(&'_ mut self, _req: &mut Request, _path: &OsStr, _link: &Path) -> Result<returns::Entry> {
-        Err(libc::ENOSYS)
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// Bind an already registered inode to a new name in this
+    /// resource, assuming it's a directory. Should fail with `EEXIST`
+    /// if the name is already taken.
+    fn link(&mut self, _req: &mut Request, _path: &OsStr, _inode: Inode) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// Remove a name from this resource, assuming it's a directory,
+    /// and return the inode it was bound to. This only removes the
+    /// name, not the resource it pointed to.
+    fn unlink(&mut self, _req: &mut Request, _path: &OsStr) -> Result<Inode> {
+        Err(Error::Errno(libc::ENOSYS))
     }
 
     //  _____ _ _                                   _   _
@@ -105,17 +259,20 @@ pub trait Resource {
     /// file. May return a "file handle", which is basically a number
     /// that doesn't mean anything to anyone but this resource
     /// itself. Generally though, it's a good idea to use the file
-    /// handle to keep track of which instance is which, such as
-    /// through a raw pointer or an ID.
-    fn open(&mut self, _req: &mut Request, _flags: u32) -> Result<FileHandle> {
-        Ok(FileHandle(0))
+    /// handle to keep track of which instance is which, see
+    /// `HandleTable` or `StatefulFile`. The returned flags can ask the
+    /// kernel to, for example, bypass its page cache. The caller's
+    /// permissions for `flags.access()` have already been checked
+    /// against `getattr`.
+    fn open(&mut self, _req: &mut Request, _flags: OpenFlags) -> Result<returns::Opened> {
+        Ok(FileHandle(0).into())
     }
 
     /// Close an instance of this resource, assuming it's a
     /// file. Files are reference counted, but this will only be
     /// called once the final copy of a file is closed. Any errors are
     /// ignored by FUSE.
-    fn close(&mut self, _req: &mut Request, _fh: FileHandle, _flags: u32) -> Result<()> {
+    fn close(&mut self, _req: &mut Request, _fh: FileHandle, _flags: OpenFlags) -> Result<()> {
         Ok(())
     }
 
@@ -123,7 +280,34 @@ pub trait Resource {
     /// buffer, assuming it's a file. Should return the number of
     /// bytes read, which must never be more than `buf.len()`.
     fn read(&'_ mut self, _req: &mut Request, _fh: FileHandle, _offset: i64, _len: u32) -> Result<Cow<'_, [u8]>> {
-        Err(libc::ENOSYS)
+        Err(Error::Errno(libc::ENOSYS))
+    }
+
+    // __  __     _   _                                     _   _
+    // \ \/ /__ _| |_| |_ _ __    ___  _ __   ___ _ __ __ _| |_(_) ___  _ __  ___
+    //  \  // _` | __| __| '__|  / _ \| '_ \ / _ \ '__/ _` | __| |/ _ \| '_ \/ __|
+    //  /  \ (_| | |_| |_| |    | (_) | |_) |  __/ | | (_| | |_| | (_) | | | \__ \
+    // /_/\_\__,_|\__|\__|_|     \___/| .__/ \___|_|  \__,_|\__|_|\___/|_| |_|___/
+    //                                |_|
+
+    /// Get the value of an extended attribute, or `ENODATA` if it's
+    /// not set. The POSIX ACL ones are also used for permission
+    /// checks, see the `acl` module.
+    fn getxattr(&'_ mut self, _req: &mut Request, _name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// Set the value of an extended attribute
+    fn setxattr(&mut self, _req: &mut Request, _name: &OsStr, _value: &[u8], _flags: XattrFlags) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// List the names of all extended attributes that are set
+    fn listxattr(&mut self, _req: &mut Request, _output: &mut Vec<OsString>) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// Remove an extended attribute, or fail with `ENODATA` if it's
+    /// not set
+    fn removexattr(&mut self, _req: &mut Request, _name: &OsStr) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
     }
 }
 
@@ -136,16 +320,32 @@ pub trait File {
     /// implemented.
     fn getattr(&mut self, _req: &mut Request) -> Result<returns::Attr>;
     /// See `Resource::open`
-    fn open(&mut self, _req: &mut Request, _flags: u32) -> Result<FileHandle> {
-        Ok(FileHandle(0))
+    fn open(&mut self, _req: &mut Request, _flags: OpenFlags) -> Result<returns::Opened> {
+        Ok(FileHandle(0).into())
     }
     /// See `Resource::close`
-    fn close(&mut self, _req: &mut Request, _fh: FileHandle, _flags: u32) -> Result<()> {
+    fn close(&mut self, _req: &mut Request, _fh: FileHandle, _flags: OpenFlags) -> Result<()> {
         Ok(())
     }
     /// See `Resource::read`
     fn read(&'_ mut self, _req: &mut Request, _fh: FileHandle, _offset: i64, _len: u32) -> Result<Cow<'_, [u8]>> {
-        Err(libc::ENOSYS)
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::getxattr`
+    fn getxattr(&'_ mut self, _req: &mut Request, _name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::setxattr`
+    fn setxattr(&mut self, _req: &mut Request, _name: &OsStr, _value: &[u8], _flags: XattrFlags) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::listxattr`
+    fn listxattr(&mut self, _req: &mut Request, _output: &mut Vec<OsString>) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::removexattr`
+    fn removexattr(&mut self, _req: &mut Request, _name: &OsStr) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
     }
 }
 
@@ -169,27 +369,57 @@ impl<F: File> Resource for FileResource<F> {
 
     // Directory operations
 
+    fn opendir(&mut self, _req: &mut Request, _flags: u32) -> Result<FileHandle> {
+        Err(Error::Errno(libc::EBADF))
+    }
+    fn releasedir(&mut self, _req: &mut Request, _fh: FileHandle, _flags: u32) -> Result<()> {
+        Err(Error::Errno(libc::EBADF))
+    }
     fn lookup(&mut self, _req: &mut Request, _path: &OsStr) -> Result<returns::Entry> {
-        Err(libc::EBADF)
+        Err(Error::Errno(libc::EBADF))
     }
-    fn readdir(&mut self, _req: &mut Request, _output: &mut Vec<returns::DirEntry>) -> Result<()> {
-        Err(libc::EBADF)
+    fn readdir(&mut self, _req: &mut Request, _fh: FileHandle, _output: &mut Vec<returns::DirEntry>) -> Result<()> {
+        Err(Error::Errno(libc::EBADF))
+    }
+    fn readdir_from(&mut self, _req: &mut Request, _fh: FileHandle, _offset: i64, _output: &mut returns::DirWriter) -> Result<()> {
+        Err(Error::Errno(libc::EBADF))
     }
     fn symlink(&'_ mut self, _req: &mut Request, _path: &OsStr, _link: &Path) -> Result<returns::Entry> {
-        Err(libc::EBADF)
+        Err(Error::Errno(libc::EBADF))
+    }
+    fn link(&mut self, _req: &mut Request, _path: &OsStr, _inode: Inode) -> Result<()> {
+        Err(Error::Errno(libc::EBADF))
+    }
+    fn unlink(&mut self, _req: &mut Request, _path: &OsStr) -> Result<Inode> {
+        Err(Error::Errno(libc::EBADF))
     }
 
     // File operations
 
-    fn open(&mut self, req: &mut Request, flags: u32) -> Result<FileHandle> {
+    fn open(&mut self, req: &mut Request, flags: OpenFlags) -> Result<returns::Opened> {
         self.0.open(req, flags)
     }
-    fn close(&mut self, req: &mut Request, fh: FileHandle, flags: u32) -> Result<()> {
+    fn close(&mut self, req: &mut Request, fh: FileHandle, flags: OpenFlags) -> Result<()> {
         self.0.close(req, fh, flags)
     }
     fn read(&'_ mut self, req: &mut Request, fh: FileHandle, offset: i64, len: u32) -> Result<Cow<'_, [u8]>> {
         self.0.read(req, fh, offset, len)
     }
+
+    // Xattr operations
+
+    fn getxattr(&'_ mut self, req: &mut Request, name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        self.0.getxattr(req, name)
+    }
+    fn setxattr(&mut self, req: &mut Request, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        self.0.setxattr(req, name, value, flags)
+    }
+    fn listxattr(&mut self, req: &mut Request, output: &mut Vec<OsString>) -> Result<()> {
+        self.0.listxattr(req, output)
+    }
+    fn removexattr(&mut self, req: &mut Request, name: &OsStr) -> Result<()> {
+        self.0.removexattr(req, name)
+    }
 }
 
 /// Abstraction on top of resource that errors on any attempt to use a
@@ -200,17 +430,55 @@ pub trait Directory {
     /// implementation because most GNU tools fail if this isn't
     /// implemented.
     fn getattr(&mut self, _req: &mut Request) -> Result<returns::Attr>;
+    /// See `Resource::opendir`
+    fn opendir(&mut self, _req: &mut Request, _flags: u32) -> Result<FileHandle> {
+        Ok(FileHandle(0))
+    }
+    /// See `Resource::releasedir`
+    fn releasedir(&mut self, _req: &mut Request, _fh: FileHandle, _flags: u32) -> Result<()> {
+        Ok(())
+    }
     /// See `Resource::lookup`
     fn lookup(&mut self, _req: &mut Request, _path: &OsStr) -> Result<returns::Entry> {
-        Err(libc::ENOSYS)
+        Err(Error::Errno(libc::ENOSYS))
     }
     /// See `Resource::readdir`
-    fn readdir(&mut self, _req: &mut Request, _output: &mut Vec<returns::DirEntry>) -> Result<()> {
-        Err(libc::ENOSYS)
+    fn readdir(&mut self, _req: &mut Request, _fh: FileHandle, _output: &mut Vec<returns::DirEntry>) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::readdir_from`
+    fn readdir_from(&mut self, req: &mut Request, fh: FileHandle, offset: i64, output: &mut returns::DirWriter) -> Result<()> {
+        let mut entries = Vec::new();
+        self.readdir(req, fh, &mut entries)?;
+        output.push_positional(entries, offset)
     }
     /// See `Resource::symlink`
     fn symlink(&'_ mut self, _req: &mut Request, _path: &OsStr, _link: &Path) -> Result<returns::Entry> {
-        Err(libc::ENOSYS)
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::link`
+    fn link(&mut self, _req: &mut Request, _path: &OsStr, _inode: Inode) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::unlink`
+    fn unlink(&mut self, _req: &mut Request, _path: &OsStr) -> Result<Inode> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::getxattr`
+    fn getxattr(&'_ mut self, _req: &mut Request, _name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::setxattr`
+    fn setxattr(&mut self, _req: &mut Request, _name: &OsStr, _value: &[u8], _flags: XattrFlags) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::listxattr`
+    fn listxattr(&mut self, _req: &mut Request, _output: &mut Vec<OsString>) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::removexattr`
+    fn removexattr(&mut self, _req: &mut Request, _name: &OsStr) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
     }
 }
 
@@ -234,25 +502,55 @@ impl<D: Directory> Resource for DirectoryResource<D> {
 
     // Directory operations
 
+    fn opendir(&mut self, req: &mut Request, flags: u32) -> Result<FileHandle> {
+        self.0.opendir(req, flags)
+    }
+    fn releasedir(&mut self, req: &mut Request, fh: FileHandle, flags: u32) -> Result<()> {
+        self.0.releasedir(req, fh, flags)
+    }
     fn lookup(&mut self, req: &mut Request, path: &OsStr) -> Result<returns::Entry> {
         self.0.lookup(req, path)
     }
-    fn readdir(&mut self, req: &mut Request, output: &mut Vec<returns::DirEntry>) -> Result<()> {
-        self.0.readdir(req, output)
+    fn readdir(&mut self, req: &mut Request, fh: FileHandle, output: &mut Vec<returns::DirEntry>) -> Result<()> {
+        self.0.readdir(req, fh, output)
+    }
+    fn readdir_from(&mut self, req: &mut Request, fh: FileHandle, offset: i64, output: &mut returns::DirWriter) -> Result<()> {
+        self.0.readdir_from(req, fh, offset, output)
     }
     fn symlink(&'_ mut self, req: &mut Request, path: &OsStr, link: &Path) -> Result<returns::Entry> {
         self.0.symlink(req, path, link)
     }
+    fn link(&mut self, req: &mut Request, path: &OsStr, inode: Inode) -> Result<()> {
+        self.0.link(req, path, inode)
+    }
+    fn unlink(&mut self, req: &mut Request, path: &OsStr) -> Result<Inode> {
+        self.0.unlink(req, path)
+    }
 
     // File operations
 
-    fn open(&mut self, _req: &mut Request, _flags: u32) -> Result<FileHandle> {
-        Err(libc::EBADF)
+    fn open(&mut self, _req: &mut Request, _flags: OpenFlags) -> Result<returns::Opened> {
+        Err(Error::Errno(libc::EBADF))
     }
-    fn close(&mut self, _req: &mut Request, _fh: FileHandle, _flags: u32) -> Result<()> {
-        Err(libc::EBADF)
+    fn close(&mut self, _req: &mut Request, _fh: FileHandle, _flags: OpenFlags) -> Result<()> {
+        Err(Error::Errno(libc::EBADF))
     }
     fn read(&'_ mut self, _req: &mut Request, _fh: FileHandle, _offset: i64, _len: u32) -> Result<Cow<'_, [u8]>> {
-        Err(libc::EBADF)
+        Err(Error::Errno(libc::EBADF))
+    }
+
+    // Xattr operations
+
+    fn getxattr(&'_ mut self, req: &mut Request, name: &OsStr) -> Result<Cow<'_, [u8]>> {
+        self.0.getxattr(req, name)
+    }
+    fn setxattr(&mut self, req: &mut Request, name: &OsStr, value: &[u8], flags: XattrFlags) -> Result<()> {
+        self.0.setxattr(req, name, value, flags)
+    }
+    fn listxattr(&mut self, req: &mut Request, output: &mut Vec<OsString>) -> Result<()> {
+        self.0.listxattr(req, output)
+    }
+    fn removexattr(&mut self, req: &mut Request, name: &OsStr) -> Result<()> {
+        self.0.removexattr(req, name)
     }
 }
@@ -23,3 +23,75 @@ bitflags! {
         const READ    = 1 << 2;
     }
 }
+
+/// Open flags are passed around as a `c_int`, but they're just a bit
+/// pattern
+#[allow(clippy::cast_sign_loss)]
+const fn flag(flag: libc::c_int) -> u32 {
+    flag as u32
+}
+
+bitflags! {
+    /// The flags a file was opened with. Read-only access has no bit
+    /// of its own, so use `access` rather than checking these
+    /// directly.
+    pub struct OpenFlags: u32 {
+        /// Open for writing only
+        const WRONLY   = flag(libc::O_WRONLY);
+        /// Open for reading and writing
+        const RDWR     = flag(libc::O_RDWR);
+        /// Every write appends to the end of the file
+        const APPEND   = flag(libc::O_APPEND);
+        /// Truncate the file to zero length
+        const TRUNC    = flag(libc::O_TRUNC);
+        /// Used with `O_CREAT`, fail if the file already exists
+        const EXCL     = flag(libc::O_EXCL);
+        /// Don't block on reads and writes
+        const NONBLOCK = flag(libc::O_NONBLOCK);
+        /// Writes return only once the data is on disk
+        const SYNC     = flag(libc::O_SYNC);
+    }
+}
+impl OpenFlags {
+    /// The access mode part of these flags
+    pub fn access(self) -> AccessMode {
+        if self.contains(Self::RDWR) {
+            AccessMode::ReadWrite
+        } else if self.contains(Self::WRONLY) {
+            AccessMode::WriteOnly
+        } else {
+            AccessMode::ReadOnly
+        }
+    }
+}
+
+/// Whether a file was opened for reading, writing, or both
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessMode {
+    /// Like `O_RDONLY`
+    ReadOnly,
+    /// Like `O_WRONLY`
+    WriteOnly,
+    /// Like `O_RDWR`
+    ReadWrite,
+}
+impl AccessMode {
+    /// The permissions needed to open a file with this access mode
+    pub fn permissions(self) -> Permissions {
+        match self {
+            AccessMode::ReadOnly => Permissions::READ,
+            AccessMode::WriteOnly => Permissions::WRITE,
+            AccessMode::ReadWrite => Permissions::READ | Permissions::WRITE,
+        }
+    }
+}
+
+bitflags! {
+    /// Flags passed to `setxattr`
+    pub struct XattrFlags: u32 {
+        /// Fail with `EEXIST` if the attribute already exists
+        const CREATE  = flag(libc::XATTR_CREATE);
+        /// Fail with `ENODATA` if the attribute doesn't exist
+        const REPLACE = flag(libc::XATTR_REPLACE);
+    }
+}
@@ -0,0 +1,166 @@
+//! An adapter for backends that think in full paths rather than in
+//! individual resources, like key-value stores or remote object
+//! listings.
+
+use crate::{
+    returns,
+    Error,
+    FileHandle,
+    Inode,
+    OpenFlags,
+    Request,
+    Resource,
+    Result,
+};
+
+use std::{
+    borrow::Cow,
+    cell::{RefCell, RefMut},
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fmt,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use fuser::FileType;
+
+/// A single entry returned from `PathFilesystem::readdir`
+#[derive(Debug, Clone)]
+pub struct PathEntry {
+    /// The name of the file, relative to the directory
+    pub name: OsString,
+    /// The type of the file
+    pub filetype: FileType,
+}
+impl PathEntry {
+    /// Create a new instance
+    pub fn new<S>(name: S, filetype: FileType) -> Self
+    where
+        S: Into<OsString>
+    {
+        Self { name: name.into(), filetype }
+    }
+}
+
+/// A whole filesystem tree implemented in terms of paths. Every path
+/// is absolute, starting at `/` for the node the `PathResource` was
+/// mounted on. Use `PathResource` to mount it inside `EasyFuse`.
+pub trait PathFilesystem {
+    /// See `Resource::getattr`. This is also used to check if a path
+    /// exists when looking it up, so return `ENOENT` for those that
+    /// don't.
+    fn getattr(&mut self, _req: &mut Request, _path: &Path) -> Result<returns::Attr>;
+    /// See `Resource::readdir`
+    fn readdir(&mut self, _req: &mut Request, _path: &Path, _output: &mut Vec<PathEntry>) -> Result<()> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+    /// See `Resource::open`
+    fn open(&mut self, _req: &mut Request, _path: &Path, _flags: OpenFlags) -> Result<returns::Opened> {
+        Ok(FileHandle(0).into())
+    }
+    /// See `Resource::close`
+    fn close(&mut self, _req: &mut Request, _path: &Path, _fh: FileHandle, _flags: OpenFlags) -> Result<()> {
+        Ok(())
+    }
+    /// See `Resource::read`
+    fn read(&'_ mut self, _req: &mut Request, _path: &Path, _fh: FileHandle, _offset: i64, _len: u32) -> Result<Cow<'_, [u8]>> {
+        Err(Error::Errno(libc::ENOSYS))
+    }
+}
+
+struct Shared<P> {
+    backend: P,
+    inodes: HashMap<PathBuf, Inode>,
+}
+
+/// A resource that forwards all operations to a `PathFilesystem`.
+/// Looking up or listing children registers a new `PathResource` for
+/// each path the first time it's seen, and caches the inode so the
+/// same path always resolves to the same inode.
+#[allow(clippy::module_name_repetitions)]
+pub struct PathResource<P: PathFilesystem> {
+    shared: Rc<RefCell<Shared<P>>>,
+    path: PathBuf,
+}
+impl<P: PathFilesystem> PathResource<P> {
+    /// Create a new instance for the root of `backend`
+    pub fn new(backend: P) -> Self {
+        Self {
+            shared: Rc::new(RefCell::new(Shared {
+                backend,
+                inodes: HashMap::new(),
+            })),
+            path: PathBuf::from("/"),
+        }
+    }
+
+    /// Getter for the path this resource represents
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Borrow the shared state, failing with `EDEADLK` if the backend
+    /// is already in use further up the stack
+    fn shared(&self) -> Result<RefMut<'_, Shared<P>>> {
+        self.shared.try_borrow_mut().map_err(|_| Error::Errno(libc::EDEADLK))
+    }
+}
+impl<P: PathFilesystem + 'static> PathResource<P> {
+    fn child(&self, req: &mut Request, name: &OsStr) -> Result<Inode> {
+        let path = self.path.join(name);
+        if let Some(&inode) = self.shared()?.inodes.get(&path) {
+            return Ok(inode);
+        }
+        let inode = req.fs.try_register(Self {
+            shared: Rc::clone(&self.shared),
+            path: path.clone(),
+        }).ok_or(Error::Errno(libc::ENOSPC))?;
+        self.shared()?.inodes.insert(path, inode);
+        Ok(inode)
+    }
+}
+impl<P: PathFilesystem> fmt::Debug for PathResource<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PathResource")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl<P: PathFilesystem + 'static> Resource for PathResource<P> {
+    fn getattr(&mut self, req: &mut Request) -> Result<returns::Attr> {
+        self.shared()?.backend.getattr(req, &self.path)
+    }
+
+    // Directory operations
+
+    fn lookup(&mut self, req: &mut Request, path: &OsStr) -> Result<returns::Entry> {
+        let mut attr = self.shared()?.backend.getattr(req, &self.path.join(path))?;
+        attr.inner.ino = self.child(req, path)?.0;
+        Ok(returns::Entry::from(attr))
+    }
+    fn readdir(&mut self, req: &mut Request, _fh: FileHandle, output: &mut Vec<returns::DirEntry>) -> Result<()> {
+        let mut entries = Vec::new();
+        self.shared()?.backend.readdir(req, &self.path, &mut entries)?;
+        for entry in entries {
+            let inode = self.child(req, &entry.name)?;
+            output.push(returns::DirEntry::new(inode, entry.filetype, entry.name));
+        }
+        Ok(())
+    }
+
+    // File operations
+
+    fn open(&mut self, req: &mut Request, flags: OpenFlags) -> Result<returns::Opened> {
+        self.shared()?.backend.open(req, &self.path, flags)
+    }
+    fn close(&mut self, req: &mut Request, fh: FileHandle, flags: OpenFlags) -> Result<()> {
+        self.shared()?.backend.close(req, &self.path, fh, flags)
+    }
+    fn read(&'_ mut self, req: &mut Request, fh: FileHandle, offset: i64, len: u32) -> Result<Cow<'_, [u8]>> {
+        let mut shared = self.shared()?;
+        let data = shared.backend.read(req, &self.path, fh, offset, len)?;
+        Ok(Cow::Owned(data.into_owned()))
+    }
+}
@@ -1,21 +1,25 @@
 //! All the kinds of structures that can be returned from different
 //! resource functons
 
-use crate::Inode;
+use crate::{Error, FileHandle, Inode, Result};
 
 use std::{
     borrow::Cow,
+    convert::TryFrom,
     ffi::OsStr,
+    fmt,
+    time::Duration,
 };
 
-use fuse::{FileAttr, FileType};
-use time::Timespec;
+use bitflags::bitflags;
+use fuser::{consts, FileAttr, FileType};
 
-/// Like `fuse::ReplyAttr`
+/// Like `fuser::ReplyAttr`
 #[derive(Debug, Clone, Copy)]
 pub struct Attr {
-    /// FIXME: Document me
-    pub ttl: Timespec,
+    /// How long the kernel may cache these attributes without asking
+    /// again. See `Notifier` for invalidating them early.
+    pub ttl: Duration,
     /// The inner fuse file attributes
     pub inner: FileAttr,
 }
@@ -26,13 +30,13 @@ where
 {
     fn from(attr: T) -> Self {
         Self {
-            ttl: Timespec::new(0, 0),
+            ttl: Duration::from_secs(0),
             inner: attr.into(),
         }
     }
 }
 
-/// Like `fuse::ReplyEntry`
+/// Like `fuser::ReplyEntry`
 #[derive(Debug, Clone, Copy)]
 pub struct Entry {
     /// The inner attributes
@@ -53,7 +57,48 @@ where
     }
 }
 
-/// Like `fuse::ReplyDirectory`
+bitflags! {
+    /// Flags telling the kernel how to treat an opened file
+    pub struct OpenedFlags: u32 {
+        /// Bypass the page cache, so every read and write reaches the
+        /// resource. Needed for files whose size isn't known up front,
+        /// as the kernel otherwise stops reading at `size`.
+        const DIRECT_IO   = consts::FOPEN_DIRECT_IO;
+        /// Keep the page cache from previous opens of this file,
+        /// instead of dropping it on every open
+        const KEEP_CACHE  = consts::FOPEN_KEEP_CACHE;
+        /// The file doesn't support seeking, like a pipe
+        const NONSEEKABLE = consts::FOPEN_NONSEEKABLE;
+    }
+}
+
+/// Like `fuser::ReplyOpen`. `StatefulFile` implementations return
+/// their own handle type here, which `Stateful` turns into a
+/// `FileHandle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opened<H = FileHandle> {
+    /// The file handle, see `Resource::open`
+    pub handle: H,
+    /// Flags for how the kernel should treat this file
+    pub flags: OpenedFlags,
+}
+impl<H> Opened<H> {
+    /// A chaining function to set the flags
+    pub fn with_flags(mut self, flags: OpenedFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+}
+impl<H> From<H> for Opened<H> {
+    fn from(handle: H) -> Self {
+        Self {
+            handle,
+            flags: OpenedFlags::empty(),
+        }
+    }
+}
+
+/// Like `fuser::ReplyDirectory`
 #[derive(Debug, Clone)]
 pub struct DirEntry {
     /// The inode associated with the file
@@ -62,13 +107,126 @@ pub struct DirEntry {
     pub filetype: FileType,
     /// The name of the file
     pub name: Cow<'static, OsStr>,
+    /// A cookie pointing to right after this entry. The kernel will
+    /// pass it back to `Resource::readdir_from` to continue listing
+    /// where it left off, so it should stay valid even if the
+    /// directory changes in between. Must be positive, since zero
+    /// means the start of the directory. Listing fails with `EIO`
+    /// otherwise.
+    pub offset: i64,
+    /// The attributes of the file, if they're cheap to get while
+    /// listing. These are sent along with the entry when the kernel
+    /// uses `readdirplus`, saving it a `lookup` per entry. The `ino`
+    /// value is overwritten with `inode`.
+    pub attr: Option<Attr>,
 }
 impl DirEntry {
-    /// Create a new instance
+    /// Create a new instance, with no offset or attributes set
     pub fn new<S>(inode: Inode, filetype: FileType, name: S) -> Self
     where
         S: Into<Cow<'static, OsStr>>
     {
-        Self { inode, filetype, name: name.into() }
+        Self { inode, filetype, name: name.into(), offset: 0, attr: None }
+    }
+    /// A chaining function to set the offset cookie
+    pub fn with_offset(mut self, offset: i64) -> Self {
+        self.offset = offset;
+        self
+    }
+    /// A chaining function to set the attributes
+    pub fn with_attr<T>(mut self, attr: T) -> Self
+    where
+        T: Into<Option<Attr>>
+    {
+        self.attr = attr.into();
+        self
+    }
+}
+
+/// Like `fuser::ReplyDirectory`, where entries are added until the
+/// reply buffer is full
+pub struct DirWriter<'a> {
+    sink: &'a mut dyn FnMut(&DirEntry) -> bool,
+    full: bool,
+}
+impl<'a> DirWriter<'a> {
+    /// Create a new instance which passes each entry to `sink`. The
+    /// sink should return `false` when it's full.
+    pub fn new(sink: &'a mut dyn FnMut(&DirEntry) -> bool) -> Self {
+        Self { sink, full: false }
+    }
+
+    /// Add an entry. Returns `false` if the buffer is full, in which
+    /// case the entry was not added and the listing should stop.
+    pub fn push(&mut self, entry: DirEntry) -> bool {
+        if !self.full && !(self.sink)(&entry) {
+            self.full = true;
+        }
+        !self.full
+    }
+    /// Returns true if a previous `push` failed
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    /// Add entries that don't have stable offsets, by numbering them
+    /// after their position. The first `offset` entries are skipped.
+    pub fn push_positional<I>(&mut self, entries: I, offset: i64) -> Result<()>
+    where
+        I: IntoIterator<Item = DirEntry>
+    {
+        let skip = usize::try_from(offset).unwrap_or(0);
+        for (i, entry) in entries.into_iter().enumerate().skip(skip) {
+            let offset = i64::try_from(i).ok()
+                .and_then(|i| i.checked_add(1))
+                .ok_or(Error::Errno(libc::EOVERFLOW))?;
+            if !self.push(entry.with_offset(offset)) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Debug for DirWriter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirWriter")
+            .field("full", &self.full)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    fn entries(names: &[&'static str]) -> Vec<DirEntry> {
+        names.iter()
+            .map(|&name| DirEntry::new(Inode(2), FileType::RegularFile, OsStr::new(name)))
+            .collect()
+    }
+    fn positional(offset: i64, room: usize) -> Vec<(OsString, i64)> {
+        let mut output = Vec::new();
+        let mut sink = |entry: &DirEntry| {
+            if output.len() >= room {
+                return false;
+            }
+            output.push((entry.name.to_os_string(), entry.offset));
+            true
+        };
+        let mut writer = DirWriter::new(&mut sink);
+        writer.push_positional(entries(&["a", "b", "c"]), offset).unwrap();
+        output
+    }
+
+    #[test]
+    fn positional_offsets() {
+        let all = positional(0, 3);
+        assert_eq!(all, vec![("a".into(), 1), ("b".into(), 2), ("c".into(), 3)]);
+        // Continuing from an entry's offset starts right after it
+        assert_eq!(positional(1, 3), all[1..]);
+        assert_eq!(positional(3, 3), vec![]);
+        // A full buffer stops the listing
+        assert_eq!(positional(0, 2), all[..2]);
     }
 }
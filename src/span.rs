@@ -0,0 +1,83 @@
+//! Structured `tracing` spans for each dispatched operation, when the
+//! `tracing` feature is enabled. Without it, these are all no-ops.
+
+use crate::{Inode, Result};
+
+#[cfg(feature = "tracing")]
+use std::{convert::TryFrom, time::Instant};
+
+/// A span covering a single dispatched operation
+#[cfg(feature = "tracing")]
+pub(crate) struct OpSpan {
+    span: tracing::Span,
+    start: Instant,
+}
+#[cfg(feature = "tracing")]
+impl OpSpan {
+    pub(crate) fn new(operation: &str, inode: Inode) -> Self {
+        use tracing::field::Empty;
+        Self {
+            span: tracing::debug_span!(
+                "fuse",
+                op = operation,
+                inode = inode.0,
+                resource = Empty,
+                pid = Empty,
+                uid = Empty,
+                offset = Empty,
+                len = Empty,
+                bytes = Empty,
+                errno = Empty,
+                latency_us = Empty,
+            ),
+            start: Instant::now(),
+        }
+    }
+    pub(crate) fn in_scope<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T
+    {
+        self.span.in_scope(f)
+    }
+    /// Record the outcome and latency of the operation
+    pub(crate) fn finish<T>(self, result: &Result<T>) {
+        let latency = u64::try_from(self.start.elapsed().as_micros()).unwrap_or(u64::max_value());
+        self.span.record("latency_us", latency);
+        match result {
+            Ok(_) => tracing::debug!(parent: &self.span, latency_us = latency, "done"),
+            Err(err) => {
+                self.span.record("errno", err.errno());
+                tracing::debug!(parent: &self.span, latency_us = latency, errno = err.errno(), "failed: {}", err);
+            },
+        }
+    }
+}
+
+/// Record a field on the span of the current operation, like the
+/// calling `pid` or how many `bytes` were read
+#[cfg(feature = "tracing")]
+pub(crate) fn record<V>(field: &str, value: V)
+where
+    V: tracing::Value
+{
+    tracing::Span::current().record(field, value);
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct OpSpan;
+#[cfg(not(feature = "tracing"))]
+impl OpSpan {
+    pub(crate) fn new(_operation: &str, _inode: Inode) -> Self {
+        Self
+    }
+    pub(crate) fn in_scope<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T
+    {
+        f()
+    }
+    pub(crate) fn finish<T>(self, _result: &Result<T>) {}
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record<V>(_field: &str, _value: V) {}
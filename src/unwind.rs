@@ -0,0 +1,67 @@
+//! Catching of panics in resources, so that one buggy resource can't
+//! unwind through FUSE and take down the whole session
+
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::{Cell, RefCell},
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+use log::error;
+
+thread_local! {
+    static CATCHING: Cell<bool> = Cell::new(false);
+    static BACKTRACE: RefCell<Option<Backtrace>> = RefCell::new(None);
+}
+
+/// Chain a panic hook that, while inside `catch`, saves the backtrace
+/// of the panic instead of printing it. Panics outside of `catch` are
+/// left to the previous hook.
+fn install_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) {
+                BACKTRACE.with(|backtrace| *backtrace.borrow_mut() = Some(Backtrace::force_capture()));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// Run `f`, or log the panic along with its backtrace and return
+/// `None` if it panics. `operation` is only used for logging.
+pub(crate) fn catch<T, F>(operation: &str, f: F) -> Option<T>
+where
+    F: FnOnce() -> T
+{
+    install_hook();
+    let was_catching = CATCHING.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|catching| catching.set(was_catching));
+
+    match result {
+        Ok(value) => Some(value),
+        Err(payload) => {
+            error!("{} panicked: {}", operation, message(&*payload));
+            if let Some(backtrace) = BACKTRACE.with(|backtrace| backtrace.borrow_mut().take()) {
+                error!("panic backtrace:\n{}", backtrace);
+            }
+            None
+        },
+    }
+}